@@ -0,0 +1,165 @@
+use crate::transfer;
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+use tokio::{sync::mpsc::channel, task::spawn_blocking};
+use tokio_stream::wrappers::ReceiverStream;
+use zip::ZipArchive;
+
+/// Separates the path of an archive from the path of a file inside it.
+pub const SEPARATOR: &str = "!/";
+
+enum Kind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl Kind {
+    fn of(path: &Path) -> Option<Kind> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with(".zip") {
+            Some(Kind::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Kind::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Kind::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn is_archive(path: &Path) -> bool {
+    Kind::of(path).is_some()
+}
+
+/// The path of a member as reported in records, `archive.zip!/path/in/archive.wav`.
+pub fn member_path(archive: &Path, name: &str) -> PathBuf {
+    PathBuf::from(format!("{}{}{}", archive.display(), SEPARATOR, name))
+}
+
+/// Splits a path made by `member_path` into the archive and the member name.
+pub fn split_member_path(path: &Path) -> Option<(PathBuf, String)> {
+    let path = path.to_string_lossy();
+    let (archive, name) = path.split_once(SEPARATOR)?;
+    let archive = PathBuf::from(archive);
+    is_archive(&archive).then(|| (archive, name.to_string()))
+}
+
+fn is_audio(name: &str) -> bool {
+    mime_guess::from_path(name)
+        .first()
+        .is_some_and(|mime| mime.type_() == "audio")
+}
+
+/// Calls `each` with the name and a reader of every audio file in the
+/// archive, until it returns `false`.
+fn visit(archive: &Path, mut each: impl FnMut(&str, &mut dyn Read) -> Result<bool>) -> Result<()> {
+    let kind = Kind::of(archive).with_context(|| format!("{:?} is not an archive", archive))?;
+    let file = BufReader::new(File::open(archive)?);
+    let entries = match kind {
+        Kind::Zip => {
+            let mut zip = ZipArchive::new(file)?;
+            for index in 0..zip.len() {
+                let mut entry = zip.by_index(index)?;
+                let name = entry.name().to_string();
+                if entry.is_file() && is_audio(&name) && !each(&name, &mut entry)? {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+        Kind::Tar => Box::new(file) as Box<dyn Read>,
+        Kind::TarGz => Box::new(GzDecoder::new(file)),
+    };
+    let mut tar = tar::Archive::new(entries);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().to_string();
+        if entry.header().entry_type().is_file() && is_audio(&name) && !each(&name, &mut entry)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// An audio file read from an archive.
+pub struct Member {
+    pub path: PathBuf,
+    pub bytes: Vec<u8>,
+}
+
+/// Reads the audio files of an archive one at a time on a blocking thread.
+pub fn members(archive: PathBuf) -> ReceiverStream<Result<Member>> {
+    let (members, receiver) = channel(1);
+    spawn_blocking(move || {
+        let result = visit(&archive, |name, reader| {
+            let mut bytes = Vec::new();
+            reader
+                .read_to_end(&mut bytes)
+                .with_context(|| format!("Failed to read {}", name))?;
+            let member = Member {
+                path: member_path(&archive, name),
+                bytes,
+            };
+            Ok(members.blocking_send(Ok(member)).is_ok())
+        });
+        if let Err(e) = result {
+            let e = e.context(format!("Failed to read archive {:?}", archive));
+            _ = members.blocking_send(Err(e));
+        }
+    });
+    ReceiverStream::new(receiver)
+}
+
+/// Reads a single member of an archive.
+pub fn read_member(archive: &Path, name: &str) -> Result<Vec<u8>> {
+    let mut bytes = None;
+    visit(archive, |member, reader| {
+        if member != name {
+            return Ok(true);
+        }
+        let mut buffer = Vec::new();
+        reader
+            .read_to_end(&mut buffer)
+            .with_context(|| format!("Failed to read {}", name))?;
+        bytes = Some(buffer);
+        Ok(false)
+    })
+    .with_context(|| format!("Failed to read archive {:?}", archive))?;
+    bytes.with_context(|| format!("{:?} has no member {}", archive, name))
+}
+
+/// Reads a file, or a member of an archive given as a member path.
+pub fn contents(path: &Path) -> Result<Vec<u8>> {
+    match split_member_path(path) {
+        Some((archive, name)) => read_member(&archive, &name),
+        None => fs::read(path).with_context(|| format!("Failed to read {:?}", path)),
+    }
+}
+
+/// Writes the members named in `destinations` to each of their paths, in a
+/// single pass over the archive.
+pub fn extract(archive: &Path, destinations: &HashMap<String, Vec<PathBuf>>) -> Result<()> {
+    let mut remaining = destinations.len();
+    visit(archive, |name, reader| {
+        if let Some(paths) = destinations.get(name) {
+            let mut bytes = Vec::new();
+            reader
+                .read_to_end(&mut bytes)
+                .with_context(|| format!("Failed to read {}", name))?;
+            for destination in paths {
+                transfer::write(destination, &bytes)?;
+            }
+            remaining -= 1;
+        }
+        Ok(remaining > 0)
+    })
+    .with_context(|| format!("Failed to extract from {:?}", archive))
+}
@@ -1,14 +1,20 @@
+use crate::decoder::{decode, Decoded, SourceFormat};
 use anyhow::{Context, Error, Result};
 use async_walkdir::DirEntry;
+use clap::ValueEnum;
 use fon::{chan::Ch32, Audio};
 use itertools::Itertools;
 use knf_rs::compute_fbank;
-use rodio::{Decoder, Source};
-use std::io::Cursor;
+use serde::Serialize;
+use std::f64::consts::PI;
 
 pub const SAMPLE_RATE: u32 = 16000;
 pub const NUM_FRAMES: usize = 1024;
 pub const NUM_MEL_BINS: usize = 128;
+/// knf computes one fbank frame every 10 ms.
+pub const FRAMES_PER_SECOND: usize = 100;
+
+pub type Fbank = Box<[[f32; NUM_MEL_BINS]]>;
 
 pub async fn is_audio_file(entry: DirEntry) -> Result<bool> {
     if let Some(mime) = mime_guess::from_path(entry.path()).first() {
@@ -19,35 +25,597 @@ pub async fn is_audio_file(entry: DirEntry) -> Result<bool> {
     Ok(true)
 }
 
-fn extract_samples(decoder: Decoder<Cursor<Vec<u8>>>) -> Result<(Box<[f32]>, u32)> {
-    let channels = decoder.channels() as usize;
-    let sample_rate = decoder.sample_rate();
-    let samples = decoder
-        .map(|sample| sample as f32 / i16::MAX as f32)
+fn extract_samples(decoded: &Decoded) -> Result<Box<[f32]>> {
+    let channels = decoded.channels;
+    if channels == 0 {
+        return Err(Error::msg("Decoded audio has no channels"));
+    }
+    let samples = decoded
+        .samples
         .chunks(channels)
-        .into_iter()
-        .map(|chunk| chunk.into_iter().sum::<f32>() / channels as f32)
+        .map(|chunk| chunk.iter().sum::<f32>() / channels as f32)
         .collect_vec()
         .into_boxed_slice();
-    Ok((samples, sample_rate))
+    Ok(samples)
+}
+
+fn extract_channels(decoded: &Decoded) -> Vec<Box<[f32]>> {
+    (0..decoded.channels)
+        .map(|channel| {
+            decoded
+                .samples
+                .iter()
+                .skip(channel)
+                .step_by(decoded.channels)
+                .copied()
+                .collect_vec()
+                .into_boxed_slice()
+        })
+        .collect_vec()
+}
+
+pub struct Extracted {
+    /// All channels downmixed into one.
+    pub audio: Audio<Ch32, 1>,
+    /// Every channel on its own, only filled for multichannel files when requested.
+    pub channels: Vec<Audio<Ch32, 1>>,
+    pub metrics: Metrics,
+    pub format: SourceFormat,
+}
+
+pub fn extract_audio(
+    buffer: Vec<u8>,
+    extension: Option<&str>,
+    per_channel: bool,
+) -> Result<Extracted> {
+    let decoded = decode(buffer, extension)?;
+    let samples = extract_samples(&decoded).with_context(|| "Failed to extract samples")?;
+    let metrics = measure(
+        &decoded.samples,
+        decoded.channels,
+        decoded.sample_rate,
+        &samples,
+    );
+    let audio = Audio::with_f32_buffer(decoded.sample_rate, samples);
+    let channels = if per_channel && decoded.channels > 1 {
+        extract_channels(&decoded)
+            .into_iter()
+            .map(|samples| Audio::with_f32_buffer(decoded.sample_rate, samples))
+            .collect_vec()
+    } else {
+        Vec::new()
+    };
+    Ok(Extracted {
+        audio,
+        channels,
+        metrics,
+        format: decoded.format,
+    })
 }
 
-pub fn extract_audio(buffer: Vec<u8>) -> Result<Audio<Ch32, 1>> {
-    let cursor = Cursor::new(buffer);
-    let decoder = Decoder::new(cursor).with_context(|| "Failed to initialize decoder")?;
-    let (samples, sample_rate) =
-        extract_samples(decoder).with_context(|| "Failed to extract samples")?;
-    let audio = Audio::with_f32_buffer(sample_rate, samples);
-    Ok(audio)
+/// How audio is converted to the model's sample rate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Resampler {
+    /// fon's built-in converter
+    #[default]
+    Fon,
+    /// Hann-windowed sinc, torchaudio's default
+    SincHann,
+    /// Kaiser-windowed sinc, torchaudio's "kaiser_fast" settings
+    KaiserFast,
+    /// Kaiser-windowed sinc, torchaudio's "kaiser_best" settings
+    KaiserBest,
 }
 
-pub fn resample(audio: &mut Audio<Ch32, 1>, target_sample_rate: u32) {
-    *audio = Audio::with_audio(target_sample_rate, audio);
+enum Window {
+    Hann,
+    Kaiser { beta: f64 },
 }
 
-pub fn create_fbank(audio: &mut Audio<Ch32, 1>) -> Result<Box<[[f32; NUM_MEL_BINS]]>> {
+struct SincParameters {
+    lowpass_filter_width: f64,
+    rolloff: f64,
+    window: Window,
+}
+
+impl Resampler {
+    fn parameters(&self) -> Option<SincParameters> {
+        match self {
+            Resampler::Fon => None,
+            Resampler::SincHann => Some(SincParameters {
+                lowpass_filter_width: 6.0,
+                rolloff: 0.99,
+                window: Window::Hann,
+            }),
+            Resampler::KaiserFast => Some(SincParameters {
+                lowpass_filter_width: 16.0,
+                rolloff: 0.85,
+                window: Window::Kaiser { beta: 8.555_0 },
+            }),
+            Resampler::KaiserBest => Some(SincParameters {
+                lowpass_filter_width: 64.0,
+                rolloff: 0.947_593_7,
+                window: Window::Kaiser {
+                    beta: 14.769_656_459_379_492,
+                },
+            }),
+        }
+    }
+}
+
+/// Zeroth order modified Bessel function of the first kind.
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    for k in 1..64 {
+        term *= (x / (2.0 * k as f64)).powi(2);
+        sum += term;
+        if term < sum * f64::EPSILON {
+            break;
+        }
+    }
+    sum
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Polyphase windowed-sinc resampling, computed the same way as
+/// `torchaudio.functional.resample` so features match the training pipeline.
+fn resample_sinc(samples: &[f32], from: u32, to: u32, parameters: &SincParameters) -> Vec<f32> {
+    let divisor = gcd(from, to);
+    let (from, to) = ((from / divisor) as usize, (to / divisor) as usize);
+    let SincParameters {
+        lowpass_filter_width,
+        rolloff,
+        ref window,
+    } = *parameters;
+
+    let base = from.min(to) as f64 * rolloff;
+    let width = (lowpass_filter_width * from as f64 / base).ceil() as usize;
+    let kernels = (0..to)
+        .map(|phase| {
+            (0..2 * width + from)
+                .map(|tap| {
+                    let t = (tap as f64 - width as f64) / from as f64 - phase as f64 / to as f64;
+                    let t = (t * base).clamp(-lowpass_filter_width, lowpass_filter_width);
+                    let window = match window {
+                        Window::Hann => (t * PI / lowpass_filter_width / 2.0).cos().powi(2),
+                        Window::Kaiser { beta } => {
+                            let x = 1.0 - (t / lowpass_filter_width).powi(2);
+                            bessel_i0(beta * x.sqrt()) / bessel_i0(*beta)
+                        }
+                    };
+                    let t = t * PI;
+                    let sinc = if t == 0.0 { 1.0 } else { t.sin() / t };
+                    (sinc * window * base / from as f64) as f32
+                })
+                .collect_vec()
+        })
+        .collect_vec();
+
+    let length = (samples.len() * to).div_ceil(from);
+    (0..length)
+        .map(|index| {
+            let (frame, phase) = (index / to, index % to);
+            let kernel = &kernels[phase];
+            // The kernel starts `width` samples before the frame.
+            let start = frame * from;
+            let first = width.saturating_sub(start);
+            let last = kernel.len().min(samples.len() + width - start);
+            kernel[first..last]
+                .iter()
+                .zip(&samples[start + first - width..start + last - width])
+                .map(|(weight, sample)| weight * sample)
+                .sum()
+        })
+        .collect_vec()
+}
+
+pub fn resample(audio: &mut Audio<Ch32, 1>, target_sample_rate: u32, resampler: Resampler) {
+    let sample_rate = audio.sample_rate().get();
+    if sample_rate == target_sample_rate {
+        return;
+    }
+    match resampler.parameters() {
+        None => *audio = Audio::with_audio(target_sample_rate, audio),
+        Some(parameters) => {
+            let samples = resample_sinc(
+                audio.as_f32_slice(),
+                sample_rate,
+                target_sample_rate,
+                &parameters,
+            );
+            *audio = Audio::with_f32_buffer(target_sample_rate, samples);
+        }
+    }
+}
+
+/// Strips leading and trailing 10 ms frames whose RMS level is below
+/// `threshold` dBFS, returning `None` when no frame reaches it. The number
+/// of samples cut from the start is returned alongside.
+pub fn trim_silence(mut audio: Audio<Ch32, 1>, threshold: f32) -> Option<(Audio<Ch32, 1>, usize)> {
+    let sample_rate = audio.sample_rate().get();
+    let frame_size = (sample_rate as usize / 100).max(1);
     let samples = audio.as_f32_slice();
-    let mut fbank = compute_fbank(samples).map_err(|e| Error::msg(e.to_string()))?;
-    fbank.truncate(NUM_FRAMES);
+    let is_loud = |frame: &[f32]| {
+        let power = frame.iter().map(|sample| sample * sample).sum::<f32>() / frame.len() as f32;
+        10.0 * power.log10() >= threshold
+    };
+    let first = samples.chunks(frame_size).position(is_loud)?;
+    let last = samples.chunks(frame_size).rposition(is_loud)?;
+    let start = first * frame_size;
+    let end = ((last + 1) * frame_size).min(samples.len());
+    if start == 0 && end == samples.len() {
+        return Some((audio, 0));
+    }
+    let trimmed = samples[start..end].to_vec();
+    Some((Audio::with_f32_buffer(sample_rate, trimmed), start))
+}
+
+/// Levels of silent audio, in dB, instead of negative infinity.
+pub const FLOOR_DB: f32 = -120.0;
+/// Samples at or above this magnitude count as clipped.
+const CLIP_LEVEL: f32 = 0.999;
+
+fn to_db(power: f64) -> f32 {
+    if power > 0.0 {
+        ((10.0 * power.log10()) as f32).max(FLOOR_DB)
+    } else {
+        FLOOR_DB
+    }
+}
+
+/// Measurements of the decoded audio of a file, taken before any trimming
+/// or resampling.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Metrics {
+    /// In seconds.
+    pub duration: f64,
+    pub sample_rate: u32,
+    pub channels: usize,
+    /// Highest sample magnitude of any channel, in dBFS.
+    pub peak: f32,
+    /// Over all channels, in dBFS.
+    pub rms: f32,
+    /// Integrated loudness after ITU-R BS.1770, in LUFS.
+    pub loudness: f32,
+    /// Share of samples at full scale.
+    pub clipping: f32,
+    /// Level of the loudest 50 ms frames over the quietest, in dB.
+    pub snr: f32,
+}
+
+/// A biquad filter in direct form I.
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+}
+
+/// The last two inputs and outputs of a biquad running over one channel.
+#[derive(Debug, Clone, Copy, Default)]
+struct History {
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+impl Biquad {
+    fn next(&self, history: &mut History, x: f64) -> f64 {
+        let History { x1, x2, y1, y2 } = *history;
+        let y = self.b[0] * x + self.b[1] * x1 + self.b[2] * x2 - self.a[0] * y1 - self.a[1] * y2;
+        *history = History {
+            x1: x,
+            x2: x1,
+            y1: y,
+            y2: y1,
+        };
+        y
+    }
+}
+
+/// The two K-weighting stages of BS.1770, a high shelf and a high pass,
+/// designed for `sample_rate` rather than only the 48 kHz the standard
+/// lists coefficients for.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+
+    let k = (std::f64::consts::PI * 1681.974450955533 / rate).tan();
+    let q = 0.7071752369554196;
+    let vh = 10f64.powf(3.999843853973347 / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    };
+
+    let k = (std::f64::consts::PI * 38.13547087602444 / rate).tan();
+    let q = 0.5003270373238773;
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    };
+    [shelf, high_pass]
+}
+
+/// Gated loudness over 400 ms blocks overlapping by 75% of interleaved
+/// samples, filtered as they are read. Every channel is weighted the same,
+/// which is only exact up to stereo.
+fn loudness(samples: &[f32], channels: usize, sample_rate: u32) -> f32 {
+    let filters = k_weighting(sample_rate);
+    let mut histories = vec![[History::default(); 2]; channels];
+    let step = (sample_rate as usize / 10).max(1);
+    // Energy of the weighted channels in every 100 ms, four of which make a
+    // block.
+    let energies = samples
+        .chunks_exact(step * channels)
+        .map(|chunk| {
+            chunk
+                .chunks_exact(channels)
+                .map(|frame| {
+                    frame
+                        .iter()
+                        .zip(histories.iter_mut())
+                        .map(|(&sample, history)| {
+                            let weighted = filters
+                                .iter()
+                                .zip(history.iter_mut())
+                                .fold(sample as f64, |x, (filter, history)| {
+                                    filter.next(history, x)
+                                });
+                            weighted * weighted
+                        })
+                        .sum::<f64>()
+                })
+                .sum::<f64>()
+        })
+        .collect_vec();
+    let powers = energies
+        .windows(4)
+        .map(|energies| energies.iter().sum::<f64>() / (4 * step) as f64)
+        .collect_vec();
+    let lufs = |power: f64| -0.691 + 10.0 * power.log10();
+    let gated = |threshold: f64| {
+        let passing = powers
+            .iter()
+            .copied()
+            .filter(|&power| lufs(power) > threshold)
+            .collect_vec();
+        (!passing.is_empty()).then(|| passing.iter().sum::<f64>() / passing.len() as f64)
+    };
+    let Some(mean) = gated(-70.0) else {
+        return FLOOR_DB;
+    };
+    match gated(lufs(mean) - 10.0) {
+        Some(mean) => (lufs(mean) as f32).max(FLOOR_DB),
+        None => FLOOR_DB,
+    }
+}
+
+/// Compares the loudest tenth of 50 ms frames of the downmix with the
+/// quietest tenth, which is rough but needs no idea of what the signal is.
+fn snr(downmix: &[f32], sample_rate: u32) -> f32 {
+    let frame = (sample_rate as usize / 20).max(1);
+    let mut powers = downmix
+        .chunks(frame)
+        .map(|frame| frame.iter().map(|&x| x as f64 * x as f64).sum::<f64>() / frame.len() as f64)
+        .collect_vec();
+    if powers.is_empty() {
+        return 0.0;
+    }
+    powers.sort_by(|a, b| a.total_cmp(b));
+    let tenth = (powers.len() / 10).max(1);
+    let mean = |powers: &[f64]| powers.iter().sum::<f64>() / powers.len() as f64;
+    let noise = mean(&powers[..tenth]);
+    let signal = mean(&powers[powers.len() - tenth..]);
+    if signal <= 0.0 {
+        return 0.0;
+    }
+    to_db(signal / noise.max(signal * 1e-12))
+}
+
+/// Measures interleaved samples with `channels` channels, whose `downmix`
+/// the caller has already built, which for one channel is `samples` itself.
+pub fn measure(samples: &[f32], channels: usize, sample_rate: u32, downmix: &[f32]) -> Metrics {
+    let channels = channels.max(1);
+    let (peak, energy, clipped) =
+        samples
+            .iter()
+            .fold((0f32, 0f64, 0usize), |(peak, energy, clipped), &sample| {
+                let magnitude = sample.abs();
+                (
+                    peak.max(magnitude),
+                    energy + sample as f64 * sample as f64,
+                    clipped + (magnitude >= CLIP_LEVEL) as usize,
+                )
+            });
+    Metrics {
+        duration: (samples.len() / channels) as f64 / sample_rate as f64,
+        sample_rate,
+        channels,
+        peak: to_db(peak as f64 * peak as f64),
+        rms: to_db(energy / samples.len().max(1) as f64),
+        loudness: loudness(samples, channels, sample_rate),
+        clipping: clipped as f32 / samples.len().max(1) as f32,
+        snr: snr(downmix, sample_rate),
+    }
+}
+
+/// Frames for the whole of `audio`, which may be more than the model's
+/// `NUM_FRAMES`.
+pub fn create_fbank(audio: &mut Audio<Ch32, 1>) -> Result<Fbank> {
+    let samples = audio.as_f32_slice();
+    let fbank = compute_fbank(samples).map_err(|e| Error::msg(e.to_string()))?;
     Ok(fbank.into_boxed_slice())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{create_fbank, measure, resample, Resampler, NUM_MEL_BINS, SAMPLE_RATE};
+    use fon::{chan::Ch32, Audio};
+    use std::{f64::consts::PI, fs::read, path::Path};
+
+    const RATES: [u32; 5] = [8000, 22050, 44100, 48000, 96000];
+
+    /// Tones spread over the band every input rate can represent, all
+    /// below the 3600 Hz `generate_tones` cuts the 8 kHz input at, so the
+    /// resampled signal should match one synthesized at 16 kHz directly.
+    fn band() -> Vec<f64> {
+        (1..30).map(|i| i as f64 * 120.0).collect()
+    }
+
+    /// Tones above the 8 kHz a 16 kHz signal can hold, which resampling has
+    /// to filter out rather than fold back into the band.
+    const ABOVE: [f64; 7] = [9000.0, 10500.0, 12000.0, 13500.0, 15000.0, 18000.0, 21000.0];
+
+    /// The sum of `frequencies` below 45% of `sample_rate`, each at the same
+    /// level whatever the rate.
+    fn generate_tones(sample_rate: u32, duration: f64, frequencies: &[f64]) -> Audio<Ch32, 1> {
+        let frequencies = frequencies
+            .iter()
+            .filter(|&&frequency| frequency < sample_rate as f64 * 0.45)
+            .collect::<Vec<_>>();
+        let length = (sample_rate as f64 * duration) as usize;
+        let samples = (0..length)
+            .map(|i| {
+                let t = i as f64 / sample_rate as f64;
+                let sum = frequencies
+                    .iter()
+                    .map(|&frequency| (2.0 * PI * frequency * t).sin())
+                    .sum::<f64>();
+                (sum / 40.0) as f32
+            })
+            .collect::<Vec<_>>();
+        Audio::with_f32_buffer(sample_rate, samples)
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    /// The mean difference of the bins of `a` at most `range` below its
+    /// loudest one and the same bins of `b`.
+    fn fbank_distance(a: &[[f32; NUM_MEL_BINS]], b: &[[f32; NUM_MEL_BINS]], range: f32) -> f32 {
+        // The edge frames depend on how the filter was primed.
+        let ceiling = a.iter().flatten().copied().fold(f32::MIN, f32::max);
+        let (sum, count) = a[2..a.len() - 2]
+            .iter()
+            .zip(&b[2..b.len() - 2])
+            .flat_map(|(a, b)| a.iter().zip(b))
+            .filter(|(a, _)| **a > ceiling - range)
+            .fold((0.0, 0), |(sum, count), (a, b)| {
+                (sum + (a - b).abs(), count + 1)
+            });
+        sum / count as f32
+    }
+
+    #[test]
+    fn sinc_resampling_matches_reference_features() {
+        let mut reference = generate_tones(SAMPLE_RATE, 2.0, &band());
+        let expected = create_fbank(&mut reference).unwrap();
+
+        for sample_rate in RATES {
+            let tones = [band().as_slice(), &ABOVE].concat();
+            let mut audio = generate_tones(sample_rate, 2.0, &tones);
+            resample(&mut audio, SAMPLE_RATE, Resampler::KaiserBest);
+            assert_eq!(audio.len(), reference.len());
+
+            let fbank = create_fbank(&mut audio).unwrap();
+            assert_eq!(fbank.len(), expected.len());
+            // Bins far below the loudest one only hold rounding noise.
+            let distance = fbank_distance(&expected, &fbank, 8.0);
+            assert!(
+                distance < 0.05,
+                "{} Hz input is {} away from the reference features",
+                sample_rate,
+                distance
+            );
+        }
+    }
+
+    #[test]
+    fn sinc_resampling_suppresses_content_above_8_khz() {
+        for sample_rate in [22050, 44100, 48000, 96000] {
+            let mut audio = generate_tones(sample_rate, 2.0, &ABOVE);
+            let before = rms(audio.as_f32_slice());
+            resample(&mut audio, SAMPLE_RATE, Resampler::KaiserBest);
+            // The edges ring where the filter runs past the ends.
+            let samples = audio.as_f32_slice();
+            let after = rms(&samples[samples.len() / 10..samples.len() * 9 / 10]);
+            let attenuation = 20.0 * (after / before).log10();
+            assert!(
+                attenuation < -60.0,
+                "{} Hz input is only attenuated by {} dB",
+                sample_rate,
+                -attenuation
+            );
+        }
+    }
+
+    /// Compares with the features torchaudio computes for half a second of
+    /// the same tones, kept in `testdata/fbank-<rate>.f32`.
+    #[test]
+    fn sinc_resampling_matches_torchaudio() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+        for sample_rate in RATES {
+            let path = dir.join(format!("fbank-{}.f32", sample_rate));
+            let bytes = read(&path).unwrap_or_else(|e| panic!("{:?}: {}", path, e));
+            let expected = bytes
+                .chunks_exact(4 * NUM_MEL_BINS)
+                .map(|frame| {
+                    let mut bins = [0.0; NUM_MEL_BINS];
+                    for (bin, value) in bins.iter_mut().zip(frame.chunks_exact(4)) {
+                        *bin = f32::from_le_bytes(value.try_into().unwrap());
+                    }
+                    bins
+                })
+                .collect::<Vec<_>>();
+
+            let tones = [band().as_slice(), &ABOVE].concat();
+            let mut audio = generate_tones(sample_rate, 0.5, &tones);
+            resample(&mut audio, SAMPLE_RATE, Resampler::KaiserBest);
+            let fbank = create_fbank(&mut audio).unwrap();
+            assert_eq!(fbank.len(), expected.len());
+            // Both went through the same filter, so even the quiet bins
+            // where aliasing would show have to agree.
+            let distance = fbank_distance(&expected, &fbank, 60.0);
+            assert!(
+                distance < 0.01,
+                "{} Hz input is {} away from torchaudio's features",
+                sample_rate,
+                distance
+            );
+        }
+    }
+
+    /// BS.1770 defines a full scale 997 Hz sine in one channel at 48 kHz
+    /// as -3.01 LUFS, the same as its RMS level.
+    #[test]
+    fn sine_metrics() {
+        let sample_rate = 48000;
+        let samples = (0..sample_rate * 2)
+            .map(|i| (2.0 * PI * 997.0 * i as f64 / sample_rate as f64).sin() as f32)
+            .collect::<Vec<_>>();
+        let metrics = measure(&samples, 1, sample_rate, &samples);
+        assert!((metrics.duration - 2.0).abs() < 1e-9);
+        assert!(metrics.peak.abs() < 0.01, "peak {}", metrics.peak);
+        assert!((metrics.rms + 3.01).abs() < 0.01, "rms {}", metrics.rms);
+        assert!(
+            (metrics.loudness + 3.01).abs() < 0.1,
+            "loudness {}",
+            metrics.loudness
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Consecutive failures after which an endpoint is ejected.
+const FAILURE_THRESHOLD: u32 = 3;
+/// How long an ejected endpoint is skipped while others are healthy.
+const EJECTION_TIME: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Cycle through the endpoints in order
+    #[default]
+    RoundRobin,
+    /// Prefer the endpoint with the fewest requests in flight
+    LeastOutstanding,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    pub requests: usize,
+    pub failures: usize,
+    pub ejections: usize,
+    /// Total time spent on successful requests.
+    pub latency: Duration,
+}
+
+#[derive(Default)]
+struct Health {
+    consecutive_failures: u32,
+    ejected_until: Option<Instant>,
+}
+
+impl Health {
+    fn is_ejected(&self, now: Instant) -> bool {
+        self.ejected_until.is_some_and(|until| now < until)
+    }
+}
+
+struct Endpoint {
+    url: String,
+    outstanding: AtomicUsize,
+    health: Mutex<Health>,
+    stats: Mutex<Stats>,
+}
+
+/// Spreads labelling requests over several janitor-service instances.
+pub struct Balancer {
+    endpoints: Vec<Endpoint>,
+    strategy: Strategy,
+    next: AtomicUsize,
+}
+
+impl Balancer {
+    pub fn new(urls: Vec<String>, strategy: Strategy) -> Balancer {
+        let endpoints = urls
+            .into_iter()
+            .map(|url| Endpoint {
+                url,
+                outstanding: AtomicUsize::new(0),
+                health: Mutex::default(),
+                stats: Mutex::default(),
+            })
+            .collect();
+        Balancer {
+            endpoints,
+            strategy,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Picks an endpoint that is not in `tried`, preferring healthy ones.
+    /// Ejected endpoints are still used once every healthy one was tried.
+    pub fn acquire(&self, tried: &[usize]) -> Option<Lease<'_>> {
+        let now = Instant::now();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let candidates = (0..self.endpoints.len())
+            .map(|offset| (start + offset) % self.endpoints.len())
+            .filter(|index| !tried.contains(index))
+            .collect::<Vec<_>>();
+        let healthy = candidates
+            .iter()
+            .copied()
+            .filter(|&index| !self.endpoints[index].health.lock().unwrap().is_ejected(now))
+            .collect::<Vec<_>>();
+        let pool = if healthy.is_empty() {
+            &candidates
+        } else {
+            &healthy
+        };
+        let index = match self.strategy {
+            Strategy::RoundRobin => pool.first().copied(),
+            Strategy::LeastOutstanding => pool
+                .iter()
+                .copied()
+                .min_by_key(|&index| self.endpoints[index].outstanding.load(Ordering::Relaxed)),
+        }?;
+
+        let endpoint = &self.endpoints[index];
+        endpoint.outstanding.fetch_add(1, Ordering::Relaxed);
+        Some(Lease {
+            index,
+            endpoint,
+            start: now,
+        })
+    }
+
+    pub fn stats(&self) -> Vec<(String, Stats)> {
+        self.endpoints
+            .iter()
+            .map(|endpoint| (endpoint.url.clone(), endpoint.stats.lock().unwrap().clone()))
+            .collect()
+    }
+}
+
+impl fmt::Display for Balancer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (url, stats) in self.stats() {
+            let successes = stats.requests - stats.failures;
+            let latency = if successes > 0 {
+                stats.latency.as_secs_f64() * 1000.0 / successes as f64
+            } else {
+                0.0
+            };
+            writeln!(
+                f,
+                "{}: {} requests, {} failures, {} ejections, {:.1} ms average",
+                url, stats.requests, stats.failures, stats.ejections, latency
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// One request in flight to an endpoint.
+pub struct Lease<'a> {
+    index: usize,
+    endpoint: &'a Endpoint,
+    start: Instant,
+}
+
+impl Lease<'_> {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn url(&self) -> &str {
+        &self.endpoint.url
+    }
+
+    pub fn succeed(self) {
+        self.endpoint.health.lock().unwrap().consecutive_failures = 0;
+        let mut stats = self.endpoint.stats.lock().unwrap();
+        stats.requests += 1;
+        stats.latency += self.start.elapsed();
+    }
+
+    pub fn fail(self) {
+        let mut health = self.endpoint.health.lock().unwrap();
+        let mut stats = self.endpoint.stats.lock().unwrap();
+        stats.requests += 1;
+        stats.failures += 1;
+        health.consecutive_failures += 1;
+        if health.consecutive_failures >= FAILURE_THRESHOLD {
+            health.consecutive_failures = 0;
+            health.ejected_until = Some(Instant::now() + EJECTION_TIME);
+            stats.ejections += 1;
+        }
+    }
+}
+
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        self.endpoint.outstanding.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{
+    audio::{Fbank, NUM_MEL_BINS},
+    client::ServiceClient,
+    processing::Prediction,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use byte_slice_cast::AsByteSlice;
+use safetensors::{serialize, tensor::TensorView, Dtype};
+use serde::Deserialize;
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    spawn,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    task::spawn_blocking,
+    time::{timeout_at, Instant},
+};
+use tracing::debug;
+
+/// The service's answer for one fbank of a batch request.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Item {
+    Prediction(Prediction),
+    Error { error: String },
+}
+
+type Job = (Fbank, oneshot::Sender<Result<Prediction>>);
+
+/// Collects fbanks from concurrently processed files and sends them to the
+/// service in batch requests, so tiny files do not each pay for a round-trip.
+#[derive(Clone)]
+pub struct Batcher {
+    client: Arc<ServiceClient>,
+    jobs: UnboundedSender<Job>,
+}
+
+impl Batcher {
+    /// A batch is sent once it holds `size` fbanks, or `delay` after its
+    /// first fbank arrived. Batches are collected by a task on the current
+    /// runtime.
+    pub async fn new(client: Arc<ServiceClient>, size: usize, delay: Duration) -> Batcher {
+        let (jobs, receiver) = unbounded_channel();
+        spawn(collect(receiver, client.clone(), size, delay));
+        Batcher { client, jobs }
+    }
+
+    pub fn client(&self) -> &ServiceClient {
+        &self.client
+    }
+
+    /// The client, once batches are no longer collected.
+    pub fn into_client(self) -> Arc<ServiceClient> {
+        self.client
+    }
+
+    pub async fn label(&self, fbank: Fbank) -> Result<Prediction> {
+        let (result_tx, result_rx) = oneshot::channel();
+        self.jobs
+            .send((fbank, result_tx))
+            .map_err(|_| anyhow!("Batcher stopped"))?;
+        result_rx.await.with_context(|| "Batch was dropped")?
+    }
+}
+
+async fn collect(
+    mut receiver: UnboundedReceiver<Job>,
+    client: Arc<ServiceClient>,
+    size: usize,
+    delay: Duration,
+) {
+    while let Some(job) = receiver.recv().await {
+        let mut jobs = vec![job];
+        let deadline = Instant::now() + delay;
+        while jobs.len() < size {
+            match timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(job)) => jobs.push(job),
+                _ => break,
+            }
+        }
+        debug!(fbanks = jobs.len(), "Sending batch");
+        spawn(send(client.clone(), jobs));
+    }
+}
+
+async fn send(client: Arc<ServiceClient>, jobs: Vec<Job>) {
+    let (fbanks, transmitters): (Vec<_>, Vec<_>) = jobs.into_iter().unzip();
+    match request(&client, fbanks).await {
+        Ok(items) => {
+            for (item, result_tx) in items.into_iter().zip(transmitters) {
+                let result = match item {
+                    Item::Prediction(prediction) => Ok(prediction),
+                    Item::Error { error } => Err(anyhow!(error)),
+                };
+                _ = result_tx.send(result);
+            }
+        }
+        Err(e) => {
+            let message = format!("{:#}", e);
+            for result_tx in transmitters {
+                _ = result_tx.send(Err(anyhow!(message.clone())));
+            }
+        }
+    }
+}
+
+/// The safetensors file holding `fbanks` as `fbank.0`, `fbank.1` and so on.
+fn serialize_fbanks(fbanks: &[Fbank]) -> Result<Vec<u8>> {
+    let tensors = fbanks
+        .iter()
+        .enumerate()
+        .map(|(index, fbank)| {
+            let size = vec![fbank.len(), NUM_MEL_BINS];
+            let tensor = TensorView::new(Dtype::F32, size, fbank.as_byte_slice())
+                .with_context(|| "Failed to create tensor from fbank")?;
+            Ok((format!("fbank.{}", index), tensor))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    serialize(tensors, &None).with_context(|| "Failed to serialize tensors")
+}
+
+async fn request(client: &ServiceClient, fbanks: Vec<Fbank>) -> Result<Vec<Item>> {
+    let count = fbanks.len();
+    let bytes = spawn_blocking(move || serialize_fbanks(&fbanks)).await??;
+
+    let reply = client.post(bytes).await?;
+    if !reply.status.is_success() {
+        bail!("Service responded with {}: {}", reply.status, reply.body);
+    }
+    let items: Vec<Item> =
+        serde_json::from_str(&reply.body).with_context(|| "Failed to deserialize output")?;
+    if items.len() != count {
+        bail!("Expected {} predictions but got {}", count, items.len());
+    }
+    Ok(items)
+}
@@ -0,0 +1,376 @@
+use crate::{
+    audio::{
+        create_fbank, extract_audio, resample, Fbank, Resampler, FRAMES_PER_SECOND, NUM_FRAMES,
+        SAMPLE_RATE,
+    },
+    processing::{label, serialize_fbank, Backend},
+};
+use anyhow::{bail, Context, Error, Result};
+use clap::ValueEnum;
+use fon::{chan::Ch32, Audio};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+use std::{
+    f32::consts::TAU,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::Semaphore,
+    task::{spawn_blocking, JoinSet},
+};
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stage {
+    /// Decoding files and downmixing them
+    Decode,
+    /// Converting the downmix to the model's sample rate
+    Resample,
+    /// Computing filter banks
+    Fbank,
+    /// Turning filter banks into the tensors sent to the service
+    Serialize,
+    /// Labelling filter banks with the backend
+    Inference,
+}
+
+/// An encoded file to benchmark with.
+pub struct Sample {
+    pub bytes: Vec<u8>,
+    pub extension: Option<String>,
+}
+
+/// A 16-bit PCM WAV file of `seconds` of tones and noise at 44.1 kHz in
+/// stereo, so that decoding, downmixing and resampling all have work to do.
+pub fn synthetic(seconds: f64, seed: u64) -> Sample {
+    const RATE: u32 = 44100;
+    const CHANNELS: u16 = 2;
+    let mut rng = StdRng::seed_from_u64(seed);
+    let pitch = rng.gen_range(110.0..880.0);
+    let frames = (seconds * RATE as f64) as u32;
+    let size = frames * CHANNELS as u32 * 2;
+
+    let mut bytes = Vec::with_capacity(44 + size as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + size).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&CHANNELS.to_le_bytes());
+    bytes.extend_from_slice(&RATE.to_le_bytes());
+    bytes.extend_from_slice(&(RATE * CHANNELS as u32 * 2).to_le_bytes());
+    bytes.extend_from_slice(&(CHANNELS * 2).to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&size.to_le_bytes());
+    for frame in 0..frames {
+        let time = frame as f32 / RATE as f32;
+        let tone = (TAU * pitch * time).sin() * 0.4;
+        for _ in 0..CHANNELS {
+            let sample = tone + rng.gen_range(-0.1..0.1);
+            bytes.extend_from_slice(&((sample * i16::MAX as f32) as i16).to_le_bytes());
+        }
+    }
+    Sample {
+        bytes,
+        extension: Some("wav".to_string()),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    pub stages: Vec<Stage>,
+    /// How many items are processed at once, for every stage.
+    pub concurrency: Vec<usize>,
+    /// Batch sizes tried for inference with a service.
+    pub batch_sizes: Vec<usize>,
+    pub batch_delay: Duration,
+    /// How many items every stage processes at every level.
+    pub iterations: usize,
+    pub resampler: Resampler,
+}
+
+/// How one stage did at one level of concurrency and batch size.
+#[derive(Debug, Clone, Serialize)]
+pub struct Measurement {
+    pub stage: Stage,
+    pub concurrency: usize,
+    pub batch_size: Option<usize>,
+    pub items: usize,
+    /// Items per second of wall time.
+    pub throughput: f64,
+    /// Seconds of audio per second of wall time.
+    pub realtime: f64,
+    /// Latencies of single items, in milliseconds.
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+}
+
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let batch_size = self
+            .batch_size
+            .map_or("-".to_string(), |size| size.to_string());
+        write!(
+            f,
+            "{:<10} {:>11} {:>5} {:>10.1} {:>10.1} {:>8.2} {:>8.2} {:>8.2}",
+            format!("{:?}", self.stage).to_lowercase(),
+            self.concurrency,
+            batch_size,
+            self.throughput,
+            self.realtime,
+            self.p50,
+            self.p90,
+            self.p99
+        )
+    }
+}
+
+/// The header of a table of measurements.
+pub const HEADER: &str =
+    "stage      concurrency batch     items/s x realtime   p50 ms   p90 ms   p99 ms";
+
+/// The latency below which `percent` of the sorted `latencies` fall, in
+/// milliseconds.
+fn percentile(latencies: &[Duration], percent: usize) -> f64 {
+    let rank = (latencies.len() * percent).div_ceil(100).max(1);
+    latencies[rank - 1].as_secs_f64() * 1000.0
+}
+
+/// Runs `task` on `iterations` items, cycling through `inputs`, with at most
+/// `concurrency` of them at once.
+async fn time<T, F, Fut>(
+    inputs: &Arc<Vec<T>>,
+    iterations: usize,
+    concurrency: usize,
+    task: F,
+) -> Result<(Vec<Duration>, Duration)>
+where
+    T: Send + Sync + 'static,
+    F: Fn(Arc<Vec<T>>, usize) -> Fut,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+    let start = Instant::now();
+    let mut tasks = JoinSet::new();
+    for iteration in 0..iterations {
+        let permit = semaphore.clone().acquire_owned().await?;
+        let future = task(inputs.clone(), iteration % inputs.len());
+        tasks.spawn(async move {
+            let start = Instant::now();
+            future.await?;
+            drop(permit);
+            Ok::<_, Error>(start.elapsed())
+        });
+    }
+    let mut latencies = Vec::with_capacity(iterations);
+    while let Some(latency) = tasks.join_next().await {
+        latencies.push(latency??);
+    }
+    let elapsed = start.elapsed();
+    latencies.sort();
+    Ok((latencies, elapsed))
+}
+
+type BlockingFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+/// Runs a CPU-bound `task` on the blocking thread pool.
+fn blocking<T, F>(task: F) -> impl Fn(Arc<Vec<T>>, usize) -> BlockingFuture
+where
+    T: Send + Sync + 'static,
+    F: Fn(&T) -> Result<()> + Send + Sync + Copy + 'static,
+{
+    move |inputs, index| {
+        Box::pin(async move { spawn_blocking(move || task(&inputs[index])).await? })
+    }
+}
+
+struct Level {
+    stage: Stage,
+    concurrency: usize,
+    batch_size: Option<usize>,
+    /// The mean length of the inputs, in seconds.
+    seconds: f64,
+}
+
+impl Level {
+    fn measure(self, latencies: Vec<Duration>, elapsed: Duration) -> Measurement {
+        let throughput = latencies.len() as f64 / elapsed.as_secs_f64();
+        Measurement {
+            stage: self.stage,
+            concurrency: self.concurrency,
+            batch_size: self.batch_size,
+            items: latencies.len(),
+            throughput,
+            realtime: throughput * self.seconds,
+            p50: percentile(&latencies, 50),
+            p90: percentile(&latencies, 90),
+            p99: percentile(&latencies, 99),
+        }
+    }
+}
+
+/// fon's audio cannot be cloned.
+fn copy(audio: &Audio<Ch32, 1>) -> Audio<Ch32, 1> {
+    let samples = audio
+        .iter()
+        .map(|frame| f32::from(frame.channels()[0]))
+        .collect::<Vec<_>>();
+    Audio::with_f32_buffer(audio.sample_rate().get(), samples)
+}
+
+fn mean(values: impl ExactSizeIterator<Item = f64>) -> f64 {
+    let count = values.len().max(1);
+    values.sum::<f64>() / count as f64
+}
+
+/// Prepares the inputs of every stage from the output of the one before,
+/// then times the requested stages at every level, calling `report` with
+/// each measurement as it is done. Inference needs `backend`.
+pub async fn bench(
+    samples: Vec<Sample>,
+    backend: Option<&Backend>,
+    options: &BenchOptions,
+    mut report: impl FnMut(&Measurement),
+) -> Result<Vec<Measurement>> {
+    let resampler = options.resampler;
+    let (samples, decoded): (Vec<_>, Vec<_>) = spawn_blocking(move || {
+        samples
+            .into_iter()
+            .filter_map(|sample| {
+                match extract_audio(sample.bytes.clone(), sample.extension.as_deref(), false) {
+                    Ok(extracted) => Some((sample, extracted.audio)),
+                    Err(e) => {
+                        warn!("Skipping a sample that failed to decode: {:#}", e);
+                        None
+                    }
+                }
+            })
+            .unzip()
+    })
+    .await?;
+    if samples.is_empty() {
+        bail!("No sample could be decoded");
+    }
+    let samples = Arc::new(samples);
+    let seconds = mean(
+        decoded
+            .iter()
+            .map(|audio| audio.len() as f64 / audio.sample_rate().get() as f64),
+    );
+    let decoded = Arc::new(decoded);
+    let resampled = spawn_blocking({
+        let decoded = decoded.clone();
+        move || {
+            decoded
+                .iter()
+                .map(|audio| {
+                    let mut audio = copy(audio);
+                    resample(&mut audio, SAMPLE_RATE, resampler);
+                    audio
+                })
+                .collect::<Vec<_>>()
+        }
+    })
+    .await?;
+    let resampled = Arc::new(resampled);
+    let fbanks = spawn_blocking({
+        let resampled = resampled.clone();
+        move || -> Result<Vec<Fbank>> {
+            resampled
+                .iter()
+                .map(|audio| {
+                    let fbank = create_fbank(&mut copy(audio))?;
+                    let length = fbank.len().min(NUM_FRAMES);
+                    Ok(fbank[..length].into())
+                })
+                .collect()
+        }
+    })
+    .await?
+    .with_context(|| "Failed to create a filter bank")?;
+    let fbank_seconds = mean(
+        fbanks
+            .iter()
+            .map(|fbank| fbank.len() as f64 / FRAMES_PER_SECOND as f64),
+    );
+    let fbanks = Arc::new(fbanks);
+
+    let mut measurements = Vec::new();
+    for &stage in &options.stages {
+        for &concurrency in &options.concurrency {
+            let level = |batch_size| Level {
+                stage,
+                concurrency,
+                batch_size,
+                seconds: match stage {
+                    Stage::Serialize | Stage::Inference => fbank_seconds,
+                    _ => seconds,
+                },
+            };
+            let iterations = options.iterations;
+            let (latencies, elapsed) = match stage {
+                Stage::Decode => {
+                    let task = blocking::<Sample, _>(|sample| {
+                        extract_audio(sample.bytes.clone(), sample.extension.as_deref(), false)
+                            .map(drop)
+                    });
+                    time(&samples, iterations, concurrency, task).await?
+                }
+                // Resampling and fbanks work on copies, as both change the audio.
+                Stage::Resample => {
+                    let task = blocking::<Audio<Ch32, 1>, _>(move |audio| {
+                        resample(&mut copy(audio), SAMPLE_RATE, resampler);
+                        Ok(())
+                    });
+                    time(&decoded, iterations, concurrency, task).await?
+                }
+                Stage::Fbank => {
+                    let task = blocking::<Audio<Ch32, 1>, _>(|audio| {
+                        create_fbank(&mut copy(audio)).map(drop)
+                    });
+                    time(&resampled, iterations, concurrency, task).await?
+                }
+                Stage::Serialize => {
+                    let task = blocking::<Fbank, _>(|fbank| serialize_fbank(fbank).map(drop));
+                    time(&fbanks, iterations, concurrency, task).await?
+                }
+                Stage::Inference => {
+                    let backend = backend.with_context(|| "Inference needs a backend")?;
+                    let batch_sizes = match backend {
+                        Backend::Service(_) | Backend::Batched(_) => options.batch_sizes.clone(),
+                        #[cfg(feature = "local")]
+                        Backend::Local(_) => vec![1],
+                    };
+                    for batch_size in batch_sizes {
+                        let backend = backend
+                            .clone()
+                            .unbatched()
+                            .batched(batch_size, options.batch_delay)
+                            .await;
+                        let task = move |fbanks: Arc<Vec<Fbank>>, index: usize| {
+                            let backend = backend.clone();
+                            async move { label(fbanks[index].clone(), &backend).await.map(drop) }
+                        };
+                        let (latencies, elapsed) =
+                            time(&fbanks, iterations, concurrency, task).await?;
+                        let measurement = level(Some(batch_size)).measure(latencies, elapsed);
+                        report(&measurement);
+                        measurements.push(measurement);
+                    }
+                    continue;
+                }
+            };
+            let measurement = level(None).measure(latencies, elapsed);
+            report(&measurement);
+            measurements.push(measurement);
+        }
+    }
+    Ok(measurements)
+}
@@ -0,0 +1,252 @@
+use crate::{balancer::Balancer, limits::default_requests};
+use anyhow::{anyhow, bail, Context, Result};
+#[cfg(unix)]
+use bytes::Bytes;
+#[cfg(unix)]
+use http_body_util::{BodyExt, Full};
+#[cfg(unix)]
+use hyper::{client::conn::http1, header, Request};
+#[cfg(unix)]
+use hyper_util::rt::TokioIo;
+use rand::Rng;
+use reqwest::{Certificate, Client, StatusCode};
+use std::{fs::read, path::PathBuf, time::Duration};
+#[cfg(unix)]
+use tokio::{net::UnixStream, spawn, time::timeout};
+use tokio::{
+    sync::Semaphore,
+    time::{sleep, Instant},
+};
+use tracing::{debug, info};
+
+/// Longest pause between two rounds of retries.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    pub connect_timeout: Duration,
+    pub request_timeout: Duration,
+    /// How many times a failed request is sent again.
+    pub retries: u32,
+    /// Pause before the first retry round, doubled for every further round.
+    pub backoff: Duration,
+    /// How many requests are in flight at once.
+    pub max_requests: usize,
+    /// Sent as a bearer token with every request.
+    pub token: Option<String>,
+    /// Extra PEM root certificate, for services with a self-signed certificate.
+    pub ca_cert: Option<PathBuf>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> ClientOptions {
+        ClientOptions {
+            connect_timeout: Duration::from_secs(10),
+            request_timeout: Duration::from_secs(60),
+            retries: 3,
+            backoff: Duration::from_millis(250),
+            max_requests: default_requests(),
+            token: None,
+            ca_cert: None,
+        }
+    }
+}
+
+impl ClientOptions {
+    /// Exponential backoff with jitter, so that many files failing at once
+    /// do not hit a recovering service in lockstep.
+    fn backoff(&self, round: u32) -> Duration {
+        let delay = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(round))
+            .min(MAX_BACKOFF);
+        delay / 2 + delay.mul_f64(rand::thread_rng().gen_range(0.0..0.5))
+    }
+}
+
+/// Turns a bare `host:port` into an HTTP URL and leaves full `http://`,
+/// `https://` and `unix://` URLs as they are. Unix sockets are only
+/// supported on Unix.
+pub fn url(address: &str) -> Result<String> {
+    if cfg!(not(unix)) && address.starts_with("unix://") {
+        bail!("Unix socket {} is not supported on this platform", address);
+    }
+    if address.contains("://") {
+        Ok(address.to_string())
+    } else {
+        Ok(format!("http://{}/", address))
+    }
+}
+
+/// A service's answer to a request.
+pub struct Reply {
+    pub status: StatusCode,
+    pub body: String,
+}
+
+/// A pooled HTTP client for janitor-service, shared by every file of a run.
+pub struct ServiceClient {
+    client: Client,
+    balancer: Balancer,
+    options: ClientOptions,
+    requests: Semaphore,
+}
+
+impl ServiceClient {
+    pub fn new(balancer: Balancer, options: ClientOptions) -> Result<ServiceClient> {
+        let mut builder = Client::builder()
+            .connect_timeout(options.connect_timeout)
+            .timeout(options.request_timeout);
+        if let Some(ref path) = options.ca_cert {
+            let pem = read(path).with_context(|| format!("Failed to read {:?}", path))?;
+            let certificate = Certificate::from_pem(&pem)
+                .with_context(|| format!("Invalid certificate in {:?}", path))?;
+            builder = builder.add_root_certificate(certificate);
+        }
+        let client = builder
+            .build()
+            .with_context(|| "Failed to build HTTP client")?;
+        let requests = Semaphore::new(options.max_requests.max(1));
+        Ok(ServiceClient {
+            client,
+            balancer,
+            options,
+            requests,
+        })
+    }
+
+    pub fn balancer(&self) -> &Balancer {
+        &self.balancer
+    }
+
+    /// Posts `bytes` to a service, failing over to other endpoints and
+    /// retrying with backoff on connection errors and 5xx responses.
+    pub async fn post(&self, bytes: Vec<u8>) -> Result<Reply> {
+        self.post_to("", bytes).await
+    }
+
+    /// Like `post`, to `route` below the service's address, e.g. `embed`.
+    pub async fn post_to(&self, route: &str, bytes: Vec<u8>) -> Result<Reply> {
+        let _permit = self
+            .requests
+            .acquire()
+            .await
+            .with_context(|| "Failed to acquire request permit")?;
+        let mut tried = Vec::new();
+        let mut round = 0;
+        let mut error = None;
+        for _ in 0..=self.options.retries {
+            let lease = match self.balancer.acquire(&tried) {
+                Some(lease) => lease,
+                None => {
+                    // Every endpoint failed in this round.
+                    tried.clear();
+                    sleep(self.options.backoff(round)).await;
+                    round += 1;
+                    self.balancer
+                        .acquire(&tried)
+                        .with_context(|| "No service endpoints")?
+                }
+            };
+            tried.push(lease.index());
+
+            let url = lease.url().to_string();
+            let started = Instant::now();
+            match self.send(&url, route, bytes.clone()).await {
+                Ok(reply) if reply.status.is_server_error() => {
+                    lease.fail();
+                    info!(
+                        %url,
+                        route,
+                        status = %reply.status,
+                        elapsed = ?started.elapsed(),
+                        "Request failed"
+                    );
+                    error = Some(anyhow!("{} responded with {}", url, reply.status));
+                }
+                Ok(reply) => {
+                    lease.succeed();
+                    debug!(
+                        %url,
+                        route,
+                        status = %reply.status,
+                        bytes = bytes.len(),
+                        elapsed = ?started.elapsed(),
+                        "Sent request"
+                    );
+                    return Ok(reply);
+                }
+                Err(e) => {
+                    lease.fail();
+                    info!(
+                        %url,
+                        route,
+                        error = %format!("{:#}", e),
+                        elapsed = ?started.elapsed(),
+                        "Request failed"
+                    );
+                    error = Some(e.context(format!("Failed to send bytes to {}", url)));
+                }
+            }
+        }
+        Err(error.unwrap_or_else(|| anyhow!("No service endpoints")))
+    }
+
+    async fn send(&self, url: &str, route: &str, bytes: Vec<u8>) -> Result<Reply> {
+        #[cfg(unix)]
+        if let Some(path) = url.strip_prefix("unix://") {
+            return timeout(
+                self.options.request_timeout,
+                self.send_unix(path, route, bytes),
+            )
+            .await
+            .with_context(|| "Request timed out")?;
+        }
+        let url = match route {
+            "" => url.to_string(),
+            route => format!("{}/{}", url.trim_end_matches('/'), route),
+        };
+        let mut request = self.client.post(url).body(bytes);
+        if let Some(ref token) = self.options.token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .with_context(|| "Failed to extract response body")?;
+        Ok(Reply { status, body })
+    }
+
+    /// reqwest cannot talk to Unix sockets, so these get a plain HTTP/1
+    /// connection per request, which is cheap on a local socket.
+    #[cfg(unix)]
+    async fn send_unix(&self, path: &str, route: &str, bytes: Vec<u8>) -> Result<Reply> {
+        let stream = timeout(self.options.connect_timeout, UnixStream::connect(path))
+            .await
+            .with_context(|| "Connection timed out")?
+            .with_context(|| format!("Failed to connect to {}", path))?;
+        let (mut sender, connection) = http1::handshake(TokioIo::new(stream)).await?;
+        spawn(connection);
+
+        let mut request = Request::post(format!("/{}", route)).header(header::HOST, "localhost");
+        if let Some(ref token) = self.options.token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let response = sender
+            .send_request(request.body(Full::new(Bytes::from(bytes)))?)
+            .await?;
+        let status = response.status();
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .with_context(|| "Failed to extract response body")?
+            .to_bytes();
+        Ok(Reply {
+            status,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        })
+    }
+}
@@ -0,0 +1,423 @@
+use crate::{
+    audio::Metrics,
+    balancer::Strategy,
+    playlist::{PlaylistFormat, PlaylistPaths},
+    processing::Label,
+};
+use anyhow::{anyhow, bail, Context, Error, Result};
+use clap::ValueEnum;
+use janitor_common::logging::LogFormat;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env::current_dir,
+    fs::read_to_string,
+    iter::once,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Name of the project-local config file, looked up from the working
+/// directory upwards.
+pub const PROJECT_CONFIG: &str = "janitor.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    /// Copy files into the directory of their label
+    Copy,
+    /// Move files into the directory of their label
+    Move,
+}
+
+/// Where `copy` and `move` put files with more than one label.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum MultiLabel {
+    /// Into the directory of the primary label only
+    #[default]
+    Primary,
+    /// Into a directory per combination, such as `speech+music`, under the combo directory
+    Combo,
+    /// Into the directory of every label
+    All,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    /// One human readable line per file
+    #[default]
+    Text,
+    /// One JSON object per file
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    Duration,
+    SampleRate,
+    Channels,
+    Peak,
+    Rms,
+    Loudness,
+    Clipping,
+    Snr,
+}
+
+impl Metric {
+    fn of(self, metrics: &Metrics) -> f64 {
+        match self {
+            Metric::Duration => metrics.duration,
+            Metric::SampleRate => metrics.sample_rate as f64,
+            Metric::Channels => metrics.channels as f64,
+            Metric::Peak => metrics.peak as f64,
+            Metric::Rms => metrics.rms as f64,
+            Metric::Loudness => metrics.loudness as f64,
+            Metric::Clipping => metrics.clipping as f64,
+            Metric::Snr => metrics.snr as f64,
+        }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Metric> {
+        Ok(match name {
+            "duration" => Metric::Duration,
+            "sample-rate" | "sample_rate" => Metric::SampleRate,
+            "channels" => Metric::Channels,
+            "peak" => Metric::Peak,
+            "rms" => Metric::Rms,
+            "loudness" => Metric::Loudness,
+            "clipping" => Metric::Clipping,
+            "snr" => Metric::Snr,
+            _ => bail!("Unknown metric {}", name),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Below,
+    AtMost,
+    AtLeast,
+    Above,
+}
+
+/// A comparison of a metric with a value, such as `clipping>0.001` or
+/// `duration<=1`, with durations in seconds and levels in dB.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Condition {
+    metric: Metric,
+    comparison: Comparison,
+    value: f64,
+}
+
+impl Condition {
+    pub fn matches(&self, metrics: &Metrics) -> bool {
+        let metric = self.metric.of(metrics);
+        match self.comparison {
+            Comparison::Below => metric < self.value,
+            Comparison::AtMost => metric <= self.value,
+            Comparison::AtLeast => metric >= self.value,
+            Comparison::Above => metric > self.value,
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = Error;
+
+    fn from_str(condition: &str) -> Result<Condition> {
+        let operators = [
+            ("<=", Comparison::AtMost),
+            (">=", Comparison::AtLeast),
+            ("<", Comparison::Below),
+            (">", Comparison::Above),
+        ];
+        let (metric, comparison, value) = operators
+            .iter()
+            .find_map(|&(operator, comparison)| {
+                let (metric, value) = condition.split_once(operator)?;
+                Some((metric, comparison, value))
+            })
+            .ok_or_else(|| {
+                anyhow!(
+                    "Expected a condition like clipping>0.001, got {}",
+                    condition
+                )
+            })?;
+        Ok(Condition {
+            metric: metric.trim().parse()?,
+            comparison,
+            value: value
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid value in {}", condition))?,
+        })
+    }
+}
+
+impl TryFrom<String> for Condition {
+    type Error = Error;
+
+    fn try_from(condition: String) -> Result<Condition> {
+        condition.parse()
+    }
+}
+
+/// The score from which a label is reported next to the primary one, such
+/// as `music=0.3`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Threshold {
+    pub label: Label,
+    pub score: f32,
+}
+
+impl FromStr for Threshold {
+    type Err = Error;
+
+    fn from_str(threshold: &str) -> Result<Threshold> {
+        let (label, score) = threshold
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Expected a threshold like music=0.3, got {}", threshold))?;
+        let label = Label::from_str(label.trim(), true)
+            .map_err(|_| anyhow!("Unknown label {}", label.trim()))?;
+        if label == Label::Silence {
+            bail!("Silence has no score to compare with a threshold");
+        }
+        Ok(Threshold {
+            label,
+            score: score
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid score in {}", threshold))?,
+        })
+    }
+}
+
+impl TryFrom<String> for Threshold {
+    type Error = Error;
+
+    fn try_from(threshold: String) -> Result<Threshold> {
+        threshold.parse()
+    }
+}
+
+/// Settings that may come from a config file, one of its profiles or the
+/// command line. Unset fields fall through to the next layer.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    pub address: Option<Vec<String>>,
+    pub balance: Option<Strategy>,
+    pub token: Option<String>,
+    pub token_file: Option<PathBuf>,
+    pub ca_cert: Option<PathBuf>,
+    pub connect_timeout: Option<String>,
+    pub request_timeout: Option<String>,
+    pub retries: Option<u32>,
+    pub backoff: Option<String>,
+    pub batch_size: Option<usize>,
+    pub batch_delay: Option<String>,
+
+    pub action: Option<Action>,
+    pub speech_dir: Option<PathBuf>,
+    pub music_dir: Option<PathBuf>,
+    pub noise_dir: Option<PathBuf>,
+    pub silence_dir: Option<PathBuf>,
+    /// Where files matching `review_if` go instead of their label's directory.
+    pub review_dir: Option<PathBuf>,
+    /// Where files with more than one label go.
+    pub multi_label: Option<MultiLabel>,
+    /// Holds a directory per combination of labels with `MultiLabel::Combo`.
+    pub combo_dir: Option<PathBuf>,
+
+    /// File of labels set by hand, used instead of the model's.
+    pub overrides: Option<PathBuf>,
+
+    /// Only label files with one of these extensions.
+    pub extensions: Option<Vec<String>>,
+    /// Scores from which labels other than the primary one are reported.
+    pub thresholds: Option<Vec<Threshold>>,
+    /// Only act on files labelled with at least this score.
+    pub min_score: Option<f32>,
+    /// Never act on files matching any of these.
+    pub skip_if: Option<Vec<Condition>>,
+    /// Send files matching any of these to `review_dir`.
+    pub review_if: Option<Vec<Condition>>,
+    /// Also label audio inside archives found while walking directories.
+    pub archives: Option<bool>,
+    /// Write audio from archives to the label directories when copying or moving.
+    pub extract: Option<bool>,
+
+    /// Where to write a playlist per label.
+    pub playlist_dir: Option<PathBuf>,
+    pub playlist_formats: Option<Vec<PlaylistFormat>>,
+    pub playlist_paths: Option<PlaylistPaths>,
+    /// Put the score of the label in playlist entry titles.
+    pub playlist_scores: Option<bool>,
+
+    pub format: Option<Format>,
+
+    /// How log events are written.
+    pub log_format: Option<LogFormat>,
+    /// Append log events to this file instead of standard error.
+    pub log_file: Option<PathBuf>,
+}
+
+impl Profile {
+    /// Fills every field unset in `self` from `fallback`.
+    pub fn or(self, fallback: Profile) -> Profile {
+        Profile {
+            address: self.address.or(fallback.address),
+            balance: self.balance.or(fallback.balance),
+            token: self.token.or(fallback.token),
+            token_file: self.token_file.or(fallback.token_file),
+            ca_cert: self.ca_cert.or(fallback.ca_cert),
+            connect_timeout: self.connect_timeout.or(fallback.connect_timeout),
+            request_timeout: self.request_timeout.or(fallback.request_timeout),
+            retries: self.retries.or(fallback.retries),
+            backoff: self.backoff.or(fallback.backoff),
+            batch_size: self.batch_size.or(fallback.batch_size),
+            batch_delay: self.batch_delay.or(fallback.batch_delay),
+            action: self.action.or(fallback.action),
+            speech_dir: self.speech_dir.or(fallback.speech_dir),
+            music_dir: self.music_dir.or(fallback.music_dir),
+            noise_dir: self.noise_dir.or(fallback.noise_dir),
+            silence_dir: self.silence_dir.or(fallback.silence_dir),
+            review_dir: self.review_dir.or(fallback.review_dir),
+            multi_label: self.multi_label.or(fallback.multi_label),
+            combo_dir: self.combo_dir.or(fallback.combo_dir),
+            overrides: self.overrides.or(fallback.overrides),
+            extensions: self.extensions.or(fallback.extensions),
+            thresholds: self.thresholds.or(fallback.thresholds),
+            min_score: self.min_score.or(fallback.min_score),
+            skip_if: self.skip_if.or(fallback.skip_if),
+            review_if: self.review_if.or(fallback.review_if),
+            archives: self.archives.or(fallback.archives),
+            extract: self.extract.or(fallback.extract),
+            playlist_dir: self.playlist_dir.or(fallback.playlist_dir),
+            playlist_formats: self.playlist_formats.or(fallback.playlist_formats),
+            playlist_paths: self.playlist_paths.or(fallback.playlist_paths),
+            playlist_scores: self.playlist_scores.or(fallback.playlist_scores),
+            format: self.format.or(fallback.format),
+            log_format: self.log_format.or(fallback.log_format),
+            log_file: self.log_file.or(fallback.log_file),
+        }
+    }
+
+    /// Resolves relative paths against the directory of the config file
+    /// they were read from, rather than the working directory.
+    fn relative_to(mut self, base: &Path) -> Profile {
+        for path in [
+            &mut self.token_file,
+            &mut self.ca_cert,
+            &mut self.speech_dir,
+            &mut self.music_dir,
+            &mut self.noise_dir,
+            &mut self.silence_dir,
+            &mut self.review_dir,
+            &mut self.combo_dir,
+            &mut self.playlist_dir,
+            &mut self.overrides,
+            &mut self.log_file,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *path = base.join(&*path);
+        }
+        self
+    }
+}
+
+/// The contents of a config file: defaults for every run, and named
+/// profiles selected with `--profile`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub defaults: Profile,
+    pub profiles: HashMap<String, Profile>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config> {
+        let text = read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+        let config: Config =
+            toml::from_str(&text).with_context(|| format!("Failed to parse {:?}", path))?;
+        // A config file must not turn a plain run into one deleting originals.
+        let mut profiles = once(&config.defaults).chain(config.profiles.values());
+        if profiles.any(|profile| profile.action == Some(Action::Move)) {
+            bail!(
+                "{:?} sets action = \"move\", but files are only moved with the move command",
+                path
+            );
+        }
+        let base = path.parent().unwrap_or(Path::new(""));
+        Ok(Config {
+            defaults: config.defaults.relative_to(base),
+            profiles: config
+                .profiles
+                .into_iter()
+                .map(|(name, profile)| (name, profile.relative_to(base)))
+                .collect(),
+        })
+    }
+
+    /// Reads the user's config and the project's `janitor.toml`, or `path`
+    /// in its place. Project settings win over user settings.
+    pub fn discover(path: Option<&Path>) -> Result<Config> {
+        let user = dirs::config_dir()
+            .map(|dir| dir.join("janitor").join("config.toml"))
+            .filter(|path| path.is_file());
+        let project = match path {
+            Some(path) => Some(path.to_path_buf()),
+            None => find_project_config()?,
+        };
+        let mut config = Config::default();
+        for path in [user, project].into_iter().flatten() {
+            config = Config::load(&path)?.over(config);
+        }
+        Ok(config)
+    }
+
+    fn over(self, base: Config) -> Config {
+        let mut profiles = base.profiles;
+        for (name, profile) in self.profiles {
+            let profile = match profiles.remove(&name) {
+                Some(base) => profile.or(base),
+                None => profile,
+            };
+            profiles.insert(name, profile);
+        }
+        Config {
+            defaults: self.defaults.or(base.defaults),
+            profiles,
+        }
+    }
+
+    /// The defaults with the named profile applied on top.
+    pub fn profile(mut self, name: Option<&str>) -> Result<Profile> {
+        let Some(name) = name else {
+            return Ok(self.defaults);
+        };
+        let profile = self
+            .profiles
+            .remove(name)
+            .with_context(|| format!("Unknown profile {}", name))?;
+        Ok(profile.or(self.defaults))
+    }
+}
+
+fn find_project_config() -> Result<Option<PathBuf>> {
+    let dir = current_dir().with_context(|| "Failed to get working directory")?;
+    Ok(dir
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG))
+        .find(|path| path.is_file()))
+}
@@ -0,0 +1,253 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+use std::{fmt, io::Cursor, io::ErrorKind};
+use symphonia::{
+    core::{
+        audio::{AudioBufferRef, SampleBuffer},
+        codecs::{self, CodecParameters, CodecType, DecoderOptions},
+        errors::Error as SymphoniaError,
+        formats::{FormatOptions, FormatReader},
+        io::MediaSourceStream,
+        meta::MetadataOptions,
+        probe::Hint,
+    },
+    default::{get_codecs, get_probe},
+};
+
+/// Codec and sample format detected while decoding a file.
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceFormat {
+    pub codec: String,
+    pub sample_format: String,
+}
+
+impl SourceFormat {
+    pub fn is_lossless(&self) -> bool {
+        self.codec.starts_with("pcm")
+            || matches!(
+                self.codec.as_str(),
+                "flac" | "alac" | "wavpack" | "tta" | "monkeys_audio"
+            )
+    }
+}
+
+impl fmt::Display for SourceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.codec, self.sample_format)
+    }
+}
+
+/// Interleaved samples of the first audio track of a file.
+pub struct Decoded {
+    pub samples: Vec<f32>,
+    pub channels: usize,
+    pub sample_rate: u32,
+    pub format: SourceFormat,
+}
+
+/// The decoder used for a track, selected from its codec rather than from
+/// whatever the container happens to be.
+enum Backend {
+    Symphonia,
+    #[cfg(feature = "opus")]
+    Opus,
+}
+
+impl Backend {
+    fn select(codec: CodecType) -> Option<Backend> {
+        match codec {
+            #[cfg(feature = "opus")]
+            codecs::CODEC_TYPE_OPUS => Some(Backend::Opus),
+            _ if get_codecs().get_codec(codec).is_some() => Some(Backend::Symphonia),
+            _ => None,
+        }
+    }
+}
+
+pub fn decode(buffer: Vec<u8>, extension: Option<&str>) -> Result<Decoded> {
+    let stream = MediaSourceStream::new(Box::new(Cursor::new(buffer)), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+    let probed = get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|e| match e {
+            SymphoniaError::Unsupported(_) => anyhow!("unsupported container format"),
+            e => anyhow!(e).context("Failed to probe container"),
+        })?;
+    let reader = probed.format;
+    let track = reader
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != codecs::CODEC_TYPE_NULL)
+        .with_context(|| "No audio track found")?;
+    let id = track.id;
+    let params = track.codec_params.clone();
+
+    match Backend::select(params.codec) {
+        Some(Backend::Symphonia) => decode_symphonia(reader, id, &params),
+        #[cfg(feature = "opus")]
+        Some(Backend::Opus) => decode_opus(reader, id, &params),
+        None => bail!("unsupported codec {}", codec_name(params.codec)),
+    }
+}
+
+fn codec_name(codec: CodecType) -> String {
+    if let Some(descriptor) = get_codecs().get_codec(codec) {
+        return descriptor.short_name.to_string();
+    }
+    let name = match codec {
+        codecs::CODEC_TYPE_OPUS => "opus",
+        codecs::CODEC_TYPE_SPEEX => "speex",
+        codecs::CODEC_TYPE_MUSEPACK => "musepack",
+        codecs::CODEC_TYPE_ATRAC1 => "atrac1",
+        codecs::CODEC_TYPE_ATRAC3 => "atrac3",
+        codecs::CODEC_TYPE_ATRAC3PLUS => "atrac3plus",
+        codecs::CODEC_TYPE_ATRAC9 => "atrac9",
+        codecs::CODEC_TYPE_EAC3 => "eac3",
+        codecs::CODEC_TYPE_AC4 => "ac4",
+        codecs::CODEC_TYPE_DCA => "dca",
+        codecs::CODEC_TYPE_WMA => "wma",
+        codecs::CODEC_TYPE_WAVPACK => "wavpack",
+        codecs::CODEC_TYPE_MONKEYS_AUDIO => "monkeys_audio",
+        codecs::CODEC_TYPE_TTA => "tta",
+        _ => return codec.to_string(),
+    };
+    name.to_string()
+}
+
+fn sample_format_name(buffer: &AudioBufferRef) -> &'static str {
+    match buffer {
+        AudioBufferRef::U8(_) => "u8",
+        AudioBufferRef::U16(_) => "u16",
+        AudioBufferRef::U24(_) => "u24",
+        AudioBufferRef::U32(_) => "u32",
+        AudioBufferRef::S8(_) => "s8",
+        AudioBufferRef::S16(_) => "s16",
+        AudioBufferRef::S24(_) => "s24",
+        AudioBufferRef::S32(_) => "s32",
+        AudioBufferRef::F32(_) => "f32",
+        AudioBufferRef::F64(_) => "f64",
+    }
+}
+
+fn is_end_of_stream(error: &SymphoniaError) -> bool {
+    matches!(error, SymphoniaError::IoError(e) if e.kind() == ErrorKind::UnexpectedEof)
+}
+
+fn decode_symphonia(
+    mut reader: Box<dyn FormatReader>,
+    id: u32,
+    params: &CodecParameters,
+) -> Result<Decoded> {
+    let codec = codec_name(params.codec);
+    let mut decoder = get_codecs()
+        .make(params, &DecoderOptions::default())
+        .map_err(|e| match e {
+            SymphoniaError::Unsupported(_) => anyhow!("unsupported codec {}", codec),
+            e => anyhow!(e).context("Failed to initialize decoder"),
+        })?;
+
+    let mut samples = Vec::new();
+    let mut channels = params.channels.map(|channels| channels.count());
+    let mut sample_rate = params.sample_rate;
+    let mut sample_format = None;
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            Err(e) if is_end_of_stream(&e) => break,
+            Err(e) => return Err(e).with_context(|| "Failed to read packet"),
+        };
+        if packet.track_id() != id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt packet only loses a few milliseconds of audio.
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e).with_context(|| "Failed to decode packet"),
+        };
+        let spec = *decoded.spec();
+        channels.get_or_insert(spec.channels.count());
+        sample_rate.get_or_insert(spec.rate);
+        sample_format.get_or_insert(sample_format_name(&decoded));
+
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        samples.extend_from_slice(buffer.samples());
+    }
+
+    Ok(Decoded {
+        samples,
+        channels: channels.with_context(|| "Unknown channel count")?,
+        sample_rate: sample_rate.with_context(|| "Unknown sample rate")?,
+        format: SourceFormat {
+            codec,
+            sample_format: sample_format.unwrap_or("unknown").to_string(),
+        },
+    })
+}
+
+#[cfg(feature = "opus")]
+fn decode_opus(
+    mut reader: Box<dyn FormatReader>,
+    id: u32,
+    params: &CodecParameters,
+) -> Result<Decoded> {
+    use audiopus::{coder::Decoder, packet::Packet, Channels, MutSignals, SampleRate};
+
+    // Opus always decodes at 48 kHz, and a packet holds at most 120 ms.
+    const OPUS_SAMPLE_RATE: u32 = 48000;
+    const MAX_FRAME_SIZE: usize = 5760;
+
+    let channels = params
+        .channels
+        .map(|channels| channels.count())
+        .unwrap_or(1);
+    let layout = match channels {
+        1 => Channels::Mono,
+        2 => Channels::Stereo,
+        _ => bail!("unsupported codec opus with {} channels", channels),
+    };
+    let mut decoder = Decoder::new(SampleRate::Hz48000, layout)
+        .with_context(|| "Failed to initialize decoder")?;
+
+    let mut samples = Vec::new();
+    let mut frame = vec![0.0; MAX_FRAME_SIZE * channels];
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            Err(e) if is_end_of_stream(&e) => break,
+            Err(e) => return Err(e).with_context(|| "Failed to read packet"),
+        };
+        if packet.track_id() != id {
+            continue;
+        }
+        let input = Packet::try_from(&packet.data[..])?;
+        let output = MutSignals::try_from(&mut frame[..])?;
+        let decoded = decoder
+            .decode_float(Some(input), output, false)
+            .with_context(|| "Failed to decode packet")?;
+        samples.extend_from_slice(&frame[..decoded * channels]);
+    }
+
+    // The encoder's pre-skip is priming data, not part of the recording.
+    let delay = params.delay.unwrap_or(0) as usize * channels;
+    samples.drain(..delay.min(samples.len()));
+
+    Ok(Decoded {
+        samples,
+        channels,
+        sample_rate: OPUS_SAMPLE_RATE,
+        format: SourceFormat {
+            codec: "opus".to_string(),
+            sample_format: "f32".to_string(),
+        },
+    })
+}
@@ -0,0 +1,181 @@
+use crate::{
+    audio::{extract_audio, Fbank, NUM_FRAMES},
+    client::ServiceClient,
+    limits::Limits,
+    processing::{file_name, serialize_fbank, to_features, Backend, Features, ProcessOptions},
+};
+#[cfg(feature = "local")]
+use anyhow::anyhow;
+use anyhow::{bail, Context, Result};
+#[cfg(feature = "local")]
+use janitor_model::{fit, from_fbank, normalize, Model};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "local")]
+use std::sync::Mutex;
+use std::{collections::HashMap, path::PathBuf};
+use tokio::task::spawn_blocking;
+
+/// What makes one copy of a sound better than another, compared field by
+/// field: lossless over lossy, then sample rate, channels and file size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Quality {
+    pub lossless: bool,
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub size: u64,
+}
+
+/// The model's pooled representation of the start of a file.
+#[derive(Debug, Clone, Serialize)]
+pub struct Embedded {
+    pub path: PathBuf,
+    pub vector: Vec<f32>,
+    pub quality: Quality,
+}
+
+#[derive(Deserialize)]
+struct Embedding {
+    vector: Vec<f32>,
+}
+
+async fn embed_remote(fbank: Fbank, client: &ServiceClient) -> Result<Vec<f32>> {
+    let bytes = spawn_blocking(move || serialize_fbank(&fbank)).await??;
+
+    let reply = client.post_to("embed", bytes).await?;
+    if !reply.status.is_success() {
+        bail!("Service responded with {}: {}", reply.status, reply.body);
+    }
+    let embedding: Embedding =
+        serde_json::from_str(&reply.body).with_context(|| "Failed to deserialize output")?;
+    Ok(embedding.vector)
+}
+
+#[cfg(feature = "local")]
+fn embed_local(fbank: &Fbank, model: &Mutex<Model>) -> Result<Vec<f32>> {
+    let mut tensor = from_fbank(fbank.as_flattened())?;
+    fit(&mut tensor)?;
+    normalize(&mut tensor)?;
+    let model = model.lock().map_err(|_| anyhow!("Model lock poisoned"))?;
+    Ok(model.embed_one(&tensor)?.vector)
+}
+
+/// Batched backends embed one fbank per request, as dedupe runs are rare
+/// enough not to need the batch format.
+async fn embed(fbank: Fbank, backend: &Backend) -> Result<Vec<f32>> {
+    match backend {
+        Backend::Service(client) => embed_remote(fbank, client).await,
+        Backend::Batched(batcher) => embed_remote(fbank, batcher.client()).await,
+        #[cfg(feature = "local")]
+        Backend::Local(model) => {
+            let model = model.clone();
+            spawn_blocking(move || embed_local(&fbank, &model)).await?
+        }
+    }
+}
+
+/// Embeds the first `NUM_FRAMES` frames of an encoded file held in memory,
+/// after trimming silence. Silent files cannot be compared and fail.
+pub async fn embed_bytes(
+    path: PathBuf,
+    buffer: Vec<u8>,
+    backend: &Backend,
+    options: &ProcessOptions,
+    limits: &Limits,
+) -> Result<Embedded> {
+    let name = file_name(&path);
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    let size = buffer.len() as u64;
+    let thread_options = options.clone();
+    let worker = limits.worker().await?;
+    let (features, quality) = spawn_blocking(move || -> Result<_> {
+        let extracted = extract_audio(buffer, extension.as_deref(), false)
+            .with_context(|| "Failed to extract audio")?;
+        let quality = Quality {
+            lossless: extracted.format.is_lossless(),
+            sample_rate: extracted.metrics.sample_rate,
+            channels: extracted.metrics.channels,
+            size,
+        };
+        Ok((to_features(extracted.audio, &thread_options)?, quality))
+    })
+    .await?
+    .with_context(|| format!("Failed to decode {}", name))?;
+    drop(worker);
+
+    let Some(Features { fbank, .. }) = features else {
+        bail!("{} is silent", name);
+    };
+    let length = fbank.len().min(NUM_FRAMES);
+    let vector = embed(fbank[..length].into(), backend)
+        .await
+        .with_context(|| format!("Failed to embed {}", name))?;
+    Ok(Embedded {
+        path,
+        vector,
+        quality,
+    })
+}
+
+/// Cosine similarity, from -1 for opposite vectors to 1 for parallel ones.
+pub fn similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot = a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+fn root(parents: &mut [usize], mut index: usize) -> usize {
+    while parents[index] != index {
+        parents[index] = parents[parents[index]];
+        index = parents[index];
+    }
+    index
+}
+
+/// Groups items linked by a chain of pairs at least `threshold` similar.
+/// Only groups of two or more are returned, as indices into `items` with
+/// the best quality first.
+pub fn cluster(items: &[Embedded], threshold: f32) -> Vec<Vec<usize>> {
+    let mut parents = (0..items.len()).collect::<Vec<_>>();
+    for a in 0..items.len() {
+        for b in a + 1..items.len() {
+            if similarity(&items[a].vector, &items[b].vector) >= threshold {
+                let (a, b) = (root(&mut parents, a), root(&mut parents, b));
+                parents[b] = a;
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for index in 0..items.len() {
+        let root = root(&mut parents, index);
+        groups.entry(root).or_default().push(index);
+    }
+    let mut clusters = groups
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .collect::<Vec<_>>();
+    for cluster in clusters.iter_mut() {
+        cluster.sort_by(|&a, &b| items[b].quality.cmp(&items[a].quality));
+    }
+    clusters.sort_by(|a, b| items[a[0]].path.cmp(&items[b[0]].path));
+    clusters
+}
+
+/// The members of a group from `cluster` that are at least `threshold`
+/// similar to its first, best member itself, rather than only through a
+/// chain of other members.
+pub fn redundant(items: &[Embedded], group: &[usize], threshold: f32) -> Vec<usize> {
+    let best = &items[group[0]].vector;
+    group[1..]
+        .iter()
+        .copied()
+        .filter(|&index| similarity(best, &items[index].vector) >= threshold)
+        .collect()
+}
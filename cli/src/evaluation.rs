@@ -0,0 +1,392 @@
+use crate::processing::{Label, Record};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Write},
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+/// Every label, in the order of the rows and columns of a confusion matrix.
+pub const LABELS: [Label; 4] = [Label::Speech, Label::Music, Label::Noise, Label::Silence];
+
+fn index(label: Label) -> usize {
+    match label {
+        Label::Speech => 0,
+        Label::Music => 1,
+        Label::Noise => 2,
+        Label::Silence => 3,
+    }
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    path: PathBuf,
+    label: String,
+}
+
+fn parse_label(label: &str) -> Result<Label> {
+    Label::from_str(label.trim(), true).map_err(|_| anyhow!("Unknown label {:?}", label))
+}
+
+/// Reads the expected label of every file from a CSV file with `path` and
+/// `label` columns, or from JSON lines with those fields. Relative paths are
+/// taken from the directory of the file.
+pub fn load_truth(path: &Path) -> Result<Vec<(PathBuf, Label)>> {
+    let text = read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    let entries = match extension.as_deref() {
+        Some("csv") => csv::Reader::from_reader(text.as_bytes())
+            .deserialize()
+            .collect::<Result<Vec<Entry>, _>>()
+            .with_context(|| format!("Failed to parse {:?}", path))?,
+        Some("jsonl" | "ndjson") => text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<Entry>, _>>()
+            .with_context(|| format!("Failed to parse {:?}", path))?,
+        _ => bail!("{:?} is neither a .csv nor a .jsonl file", path),
+    };
+    let base = path.parent().unwrap_or(Path::new(""));
+    entries
+        .into_iter()
+        .map(|entry| {
+            let label = parse_label(&entry.label)
+                .with_context(|| format!("Failed to parse the label of {:?}", entry.path))?;
+            Ok((base.join(entry.path), label))
+        })
+        .collect()
+}
+
+/// A labelled file next to its expected label.
+#[derive(Debug, Clone, Serialize)]
+pub struct Outcome {
+    pub path: PathBuf,
+    pub expected: Label,
+    pub label: Label,
+    pub score: f32,
+}
+
+impl Outcome {
+    pub fn new(record: &Record, expected: Label) -> Outcome {
+        Outcome {
+            path: record.path.clone(),
+            expected,
+            label: record.label,
+            score: record.score(),
+        }
+    }
+
+    /// Whether a threshold keeps the file. Silence carries no score and is
+    /// always kept.
+    fn kept(&self, threshold: f32) -> bool {
+        self.label == Label::Silence || self.score >= threshold
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ClassMetrics {
+    pub label: Label,
+    pub precision: f32,
+    pub recall: f32,
+    pub f1: f32,
+    /// How many files are expected to have the label.
+    pub support: usize,
+}
+
+/// How well labels match expectations.
+#[derive(Debug, Clone, Serialize)]
+pub struct Evaluation {
+    pub files: usize,
+    pub accuracy: f32,
+    pub classes: Vec<ClassMetrics>,
+    /// Counts by expected label, then by given label, in the order of
+    /// `LABELS`.
+    pub confusion: [[usize; 4]; 4],
+    pub misclassified: Vec<Outcome>,
+}
+
+fn ratio(numerator: usize, denominator: usize) -> f32 {
+    if denominator == 0 {
+        0.0
+    } else {
+        numerator as f32 / denominator as f32
+    }
+}
+
+impl Evaluation {
+    pub fn new(outcomes: &[Outcome]) -> Evaluation {
+        let mut confusion = [[0; 4]; 4];
+        for outcome in outcomes {
+            confusion[index(outcome.expected)][index(outcome.label)] += 1;
+        }
+        let correct = (0..4).map(|i| confusion[i][i]).sum();
+        let classes = LABELS
+            .iter()
+            .enumerate()
+            .map(|(i, &label)| {
+                let support = confusion[i].iter().sum();
+                let given = (0..4).map(|j| confusion[j][i]).sum();
+                let precision = ratio(confusion[i][i], given);
+                let recall = ratio(confusion[i][i], support);
+                let f1 = if precision + recall == 0.0 {
+                    0.0
+                } else {
+                    2.0 * precision * recall / (precision + recall)
+                };
+                ClassMetrics {
+                    label,
+                    precision,
+                    recall,
+                    f1,
+                    support,
+                }
+            })
+            .collect();
+        let mut misclassified = outcomes
+            .iter()
+            .filter(|outcome| outcome.label != outcome.expected)
+            .cloned()
+            .collect::<Vec<_>>();
+        misclassified.sort_by(|a, b| a.path.cmp(&b.path));
+        Evaluation {
+            files: outcomes.len(),
+            accuracy: ratio(correct, outcomes.len()),
+            classes,
+            confusion,
+            misclassified,
+        }
+    }
+
+    /// The F1 scores averaged over the labels that occur at all.
+    pub fn macro_f1(&self) -> f32 {
+        let present = self
+            .classes
+            .iter()
+            .filter(|class| class.support > 0)
+            .collect::<Vec<_>>();
+        if present.is_empty() {
+            return 0.0;
+        }
+        present.iter().map(|class| class.f1).sum::<f32>() / present.len() as f32
+    }
+}
+
+impl fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} files, accuracy {:.3}", self.files, self.accuracy)?;
+        writeln!(
+            f,
+            "\n{:<8} {:>9} {:>6} {:>6} {:>7}",
+            "", "precision", "recall", "f1", "support"
+        )?;
+        for class in &self.classes {
+            writeln!(
+                f,
+                "{:<8} {:>9.3} {:>6.3} {:>6.3} {:>7}",
+                format!("{:?}", class.label),
+                class.precision,
+                class.recall,
+                class.f1,
+                class.support
+            )?;
+        }
+        writeln!(f, "\nexpected \\ labelled")?;
+        let mut header = format!("{:<8}", "");
+        for label in LABELS {
+            _ = write!(header, " {:>7}", format!("{:?}", label));
+        }
+        writeln!(f, "{}", header)?;
+        for (label, row) in LABELS.iter().zip(&self.confusion) {
+            write!(f, "{:<8}", format!("{:?}", label))?;
+            for count in row {
+                write!(f, " {:>7}", count)?;
+            }
+            writeln!(f)?;
+        }
+        if !self.misclassified.is_empty() {
+            writeln!(f, "\nMisclassified:")?;
+            for outcome in &self.misclassified {
+                writeln!(
+                    f,
+                    "  {:?}: {:?} ({:.2}), expected {:?}",
+                    outcome.path, outcome.label, outcome.score, outcome.expected
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a minimum score would have done: files labelled with less are left
+/// alone, and the rest are evaluated.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct OperatingPoint {
+    pub threshold: f32,
+    /// The share of files kept.
+    pub coverage: f32,
+    pub accuracy: f32,
+    pub macro_f1: f32,
+}
+
+/// Tries minimum scores from 0 to 1 in `steps` steps.
+pub fn sweep(outcomes: &[Outcome], steps: usize) -> Vec<OperatingPoint> {
+    (0..=steps)
+        .map(|step| {
+            let threshold = step as f32 / steps as f32;
+            let kept = outcomes
+                .iter()
+                .filter(|outcome| outcome.kept(threshold))
+                .cloned()
+                .collect::<Vec<_>>();
+            let evaluation = Evaluation::new(&kept);
+            OperatingPoint {
+                threshold,
+                coverage: ratio(kept.len(), outcomes.len()),
+                accuracy: evaluation.accuracy,
+                macro_f1: evaluation.macro_f1(),
+            }
+        })
+        .collect()
+}
+
+/// The most accurate point that keeps at least `min_coverage` of the files,
+/// preferring lower thresholds on ties.
+pub fn best(points: &[OperatingPoint], min_coverage: f32) -> Option<OperatingPoint> {
+    points
+        .iter()
+        .filter(|point| point.coverage >= min_coverage)
+        .copied()
+        .reduce(|best, point| {
+            if point.accuracy > best.accuracy {
+                point
+            } else {
+                best
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{best, sweep, Evaluation, Outcome};
+    use crate::processing::Label::{self, Music, Noise, Silence, Speech};
+    use std::path::PathBuf;
+
+    fn outcomes(cases: &[(Label, Label, f32)]) -> Vec<Outcome> {
+        cases
+            .iter()
+            .enumerate()
+            .map(|(i, &(expected, label, score))| Outcome {
+                path: PathBuf::from(format!("{:02}.wav", i)),
+                expected,
+                label,
+                score,
+            })
+            .collect()
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn counts_and_scores_every_label() {
+        let evaluation = Evaluation::new(&outcomes(&[
+            (Speech, Speech, 0.9),
+            (Speech, Speech, 0.9),
+            (Speech, Speech, 0.9),
+            (Speech, Music, 0.6),
+            (Music, Music, 0.8),
+            (Music, Music, 0.8),
+            (Music, Speech, 0.7),
+            (Music, Noise, 0.5),
+            (Noise, Noise, 0.9),
+            (Noise, Music, 0.6),
+            (Noise, Speech, 0.6),
+        ]));
+        assert_eq!(evaluation.files, 11);
+        assert_eq!(
+            evaluation.confusion,
+            [[3, 1, 0, 0], [1, 2, 1, 0], [1, 1, 1, 0], [0, 0, 0, 0]]
+        );
+        assert_close(evaluation.accuracy, 6.0 / 11.0);
+
+        // precision, recall, f1 and support, in the order of LABELS
+        let expected = [
+            (3.0 / 5.0, 3.0 / 4.0, 2.0 / 3.0, 4),
+            (2.0 / 4.0, 2.0 / 4.0, 1.0 / 2.0, 4),
+            (1.0 / 2.0, 1.0 / 3.0, 2.0 / 5.0, 3),
+            (0.0, 0.0, 0.0, 0),
+        ];
+        for (class, (precision, recall, f1, support)) in evaluation.classes.iter().zip(expected) {
+            assert_close(class.precision, precision);
+            assert_close(class.recall, recall);
+            assert_close(class.f1, f1);
+            assert_eq!(class.support, support);
+        }
+        // Silence never occurs, so it is left out of the average.
+        assert_close(
+            evaluation.macro_f1(),
+            (2.0 / 3.0 + 1.0 / 2.0 + 2.0 / 5.0) / 3.0,
+        );
+
+        let misclassified = evaluation
+            .misclassified
+            .iter()
+            .map(|outcome| outcome.path.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            misclassified,
+            ["03.wav", "06.wav", "07.wav", "09.wav", "10.wav"]
+        );
+    }
+
+    #[test]
+    fn sweeps_thresholds_and_picks_the_most_accurate() {
+        let outcomes = outcomes(&[
+            (Speech, Speech, 0.9),
+            (Music, Music, 0.8),
+            (Noise, Noise, 0.65),
+            (Music, Noise, 0.5),
+            (Speech, Music, 0.3),
+            (Silence, Silence, 0.0),
+        ]);
+        let points = sweep(&outcomes, 10);
+        assert_eq!(points.len(), 11);
+        // kept files and how many of them are right, at 0, 0.1, …, 1
+        let expected = [
+            (6, 4),
+            (6, 4),
+            (6, 4),
+            (6, 4),
+            (5, 4),
+            (5, 4),
+            (4, 4),
+            (3, 3),
+            (3, 3),
+            (2, 2),
+            (1, 1),
+        ];
+        for (i, (point, (kept, correct))) in points.iter().zip(expected).enumerate() {
+            assert_close(point.threshold, i as f32 / 10.0);
+            assert_close(point.coverage, kept as f32 / 6.0);
+            assert_close(point.accuracy, correct as f32 / kept as f32);
+        }
+
+        let threshold = |min_coverage| best(&points, min_coverage).map(|point| point.threshold);
+        assert_eq!(threshold(0.6), Some(0.6));
+        // 0.4 and 0.5 are as accurate, and the lower one wins.
+        assert_eq!(threshold(0.8), Some(0.4));
+        assert_eq!(threshold(1.0), Some(0.0));
+        assert_eq!(best(&points[7..], 0.6).map(|point| point.threshold), None);
+    }
+}
@@ -0,0 +1,122 @@
+use crate::processing::Record;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+use std::{io::stderr, path::Path, process::Stdio};
+use tokio::process::Command;
+
+const PLACEHOLDERS: [&str; 4] = ["path", "label", "score", "dest"];
+
+/// A command line with `{path}`, `{label}`, `{score}` and `{dest}`
+/// placeholders. It is split into words like a POSIX shell would, and run
+/// without one, so values are never split or expanded again.
+#[derive(Debug, Clone)]
+pub struct Template {
+    words: Vec<String>,
+}
+
+/// Replaces the placeholders in `word` in a single pass, leaving unknown
+/// ones as they are.
+fn fill(word: &str, values: &[&str; 4]) -> String {
+    let mut filled = String::with_capacity(word.len());
+    let mut rest = word;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let placeholder = PLACEHOLDERS.iter().position(|name| {
+            rest[1..].starts_with(name) && rest[1 + name.len()..].starts_with('}')
+        });
+        match placeholder {
+            Some(index) => {
+                filled.push_str(values[index]);
+                rest = &rest[PLACEHOLDERS[index].len() + 2..];
+            }
+            None => {
+                filled.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template> {
+        let words =
+            shlex::split(template).ok_or_else(|| anyhow!("Unbalanced quotes in {}", template))?;
+        if words.is_empty() {
+            bail!("The command to run is empty");
+        }
+        Ok(Template { words })
+    }
+
+    pub fn uses_destination(&self) -> bool {
+        self.words.iter().any(|word| word.contains("{dest}"))
+    }
+
+    /// The program and its arguments for `record`, which would go to
+    /// `destination` when copied or moved.
+    pub fn render(&self, record: &Record, destination: Option<&Path>) -> Vec<String> {
+        let path = record.path.to_string_lossy();
+        let label = format!("{:?}", record.label).to_lowercase();
+        let score = format!("{:.3}", record.score());
+        let destination = destination
+            .map(|destination| destination.to_string_lossy())
+            .unwrap_or_default();
+        let values = [&*path, &*label, &*score, &*destination];
+        self.words.iter().map(|word| fill(word, &values)).collect()
+    }
+}
+
+/// How a command run for a file went.
+#[derive(Debug, Clone, Serialize)]
+pub struct Execution {
+    /// The program and its arguments, with placeholders filled in.
+    pub command: Vec<String>,
+    /// The exit code, or none if the command was killed by a signal.
+    pub status: Option<i32>,
+    pub stderr: String,
+}
+
+impl Execution {
+    pub fn success(&self) -> bool {
+        self.status == Some(0)
+    }
+}
+
+/// Runs a rendered command to completion, capturing its standard error.
+/// Its standard output goes to ours for errors, so that records stay
+/// readable on standard output.
+pub async fn run(command: Vec<String>) -> Result<Execution> {
+    let (program, args) = command
+        .split_first()
+        .with_context(|| "The command to run is empty")?;
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(stderr())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {}", program))?
+        .wait_with_output()
+        .await
+        .with_context(|| format!("Failed to wait for {}", program))?;
+    Ok(Execution {
+        status: output.status.code(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        command,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fill;
+
+    #[test]
+    fn fills_placeholders_once() {
+        let values = ["{label}.wav", "music", "0.900", ""];
+        assert_eq!(fill("{path}", &values), "{label}.wav");
+        assert_eq!(fill("{x}-{label}{", &values), "{x}-music{");
+        assert_eq!(fill("{dest}/{score}", &values), "/0.900");
+    }
+}
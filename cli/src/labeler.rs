@@ -0,0 +1,228 @@
+use crate::{
+    archive::{is_archive, members},
+    dedupe::{embed_bytes, Embedded},
+    limits::{default_open_files, default_workers, Limits},
+    processing::{
+        process, process_bytes, process_samples, read_file, Backend, ProcessOptions, Record,
+    },
+};
+use anyhow::Result;
+use std::{future::Future, path::PathBuf, pin::Pin};
+use tokio::{
+    pin, spawn,
+    sync::mpsc::{channel, Sender},
+};
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+
+#[derive(Debug, Clone)]
+pub struct LabelerOptions {
+    pub process: ProcessOptions,
+    /// How many files are held in memory at once.
+    pub max_open_files: usize,
+    /// How many files are decoded and turned into fbanks at once.
+    pub workers: usize,
+}
+
+impl Default for LabelerOptions {
+    fn default() -> LabelerOptions {
+        LabelerOptions {
+            process: ProcessOptions::default(),
+            max_open_files: default_open_files(),
+            workers: default_workers(),
+        }
+    }
+}
+
+/// Labels audio files, encoded audio in memory or raw samples.
+///
+/// Clones share the backend and the concurrency limits.
+#[derive(Clone)]
+pub struct Labeler {
+    backend: Backend,
+    options: ProcessOptions,
+    limits: Limits,
+}
+
+impl Labeler {
+    pub fn new(backend: Backend, options: LabelerOptions) -> Labeler {
+        Labeler {
+            backend,
+            options: options.process,
+            limits: Limits::new(options.max_open_files, options.workers),
+        }
+    }
+
+    pub fn backend(&self) -> &Backend {
+        &self.backend
+    }
+
+    pub async fn label_file(&self, path: impl Into<PathBuf>) -> Result<Record> {
+        let permit = self.limits.file().await?;
+        process(
+            path.into(),
+            self.backend.clone(),
+            self.options.clone(),
+            self.limits.clone(),
+            permit,
+        )
+        .await
+    }
+
+    /// Labels an encoded file held in memory. `path` names the record, and
+    /// its extension hints at the container format.
+    pub async fn label_bytes(&self, path: impl Into<PathBuf>, bytes: Vec<u8>) -> Result<Record> {
+        let _permit = self.limits.file().await?;
+        process_bytes(
+            path.into(),
+            bytes,
+            &self.backend,
+            &self.options,
+            &self.limits,
+        )
+        .await
+    }
+
+    /// Labels mono samples between -1 and 1. `name` only names the record.
+    pub async fn label_samples(
+        &self,
+        name: impl Into<PathBuf>,
+        samples: Vec<f32>,
+        sample_rate: u32,
+    ) -> Result<Record> {
+        let _permit = self.limits.file().await?;
+        process_samples(
+            name.into(),
+            samples,
+            sample_rate,
+            &self.backend,
+            &self.options,
+            &self.limits,
+        )
+        .await
+    }
+
+    /// Embeds a file for finding near-duplicates.
+    pub async fn embed_file(&self, path: impl Into<PathBuf>) -> Result<Embedded> {
+        let path = path.into();
+        let _permit = self.limits.file().await?;
+        let bytes = read_file(&path).await?;
+        embed_bytes(path, bytes, &self.backend, &self.options, &self.limits).await
+    }
+
+    /// Labels files concurrently, yielding records in the order they finish.
+    /// Archives yield a record for every audio file inside them. Paths are
+    /// only pulled from `paths` while fewer than `max_open_files` files are
+    /// in flight.
+    pub fn label_many<S>(&self, paths: S) -> ReceiverStream<Result<Record>>
+    where
+        S: Stream<Item = PathBuf> + Send + 'static,
+    {
+        self.many(paths, |labeler, path, bytes| {
+            Box::pin(async move {
+                process_bytes(
+                    path,
+                    bytes,
+                    &labeler.backend,
+                    &labeler.options,
+                    &labeler.limits,
+                )
+                .await
+            })
+        })
+    }
+
+    /// Embeds files concurrently, like `label_many`.
+    pub fn embed_many<S>(&self, paths: S) -> ReceiverStream<Result<Embedded>>
+    where
+        S: Stream<Item = PathBuf> + Send + 'static,
+    {
+        self.many(paths, |labeler, path, bytes| {
+            Box::pin(async move {
+                embed_bytes(
+                    path,
+                    bytes,
+                    &labeler.backend,
+                    &labeler.options,
+                    &labeler.limits,
+                )
+                .await
+            })
+        })
+    }
+
+    fn many<S, T>(&self, paths: S, task: Task<T>) -> ReceiverStream<Result<T>>
+    where
+        S: Stream<Item = PathBuf> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (results, receiver) = channel(1);
+        let labeler = self.clone();
+        spawn(async move {
+            pin!(paths);
+            while let Some(path) = paths.next().await {
+                if !is_archive(&path) {
+                    if !labeler.dispatch(Source::File(path), task, &results).await {
+                        return;
+                    }
+                    continue;
+                }
+                let mut members = members(path);
+                while let Some(member) = members.next().await {
+                    let source = match member {
+                        Ok(member) => Source::Bytes(member.path, member.bytes),
+                        Err(e) => {
+                            _ = results.send(Err(e)).await;
+                            continue;
+                        }
+                    };
+                    if !labeler.dispatch(source, task, &results).await {
+                        return;
+                    }
+                }
+            }
+        });
+        ReceiverStream::new(receiver)
+    }
+
+    /// Runs `task` on `source` on its own task once a file permit is free.
+    /// Returns `false` when no more work can be started.
+    async fn dispatch<T: Send + 'static>(
+        &self,
+        source: Source,
+        task: Task<T>,
+        results: &Sender<Result<T>>,
+    ) -> bool {
+        let permit = match self.limits.file().await {
+            Ok(permit) => permit,
+            Err(e) => {
+                _ = results.send(Err(e)).await;
+                return false;
+            }
+        };
+        let labeler = self.clone();
+        let results = results.clone();
+        spawn(async move {
+            let _permit = permit;
+            let (path, bytes) = match source {
+                Source::File(path) => match read_file(&path).await {
+                    Ok(bytes) => (path, bytes),
+                    Err(e) => {
+                        _ = results.send(Err(e)).await;
+                        return;
+                    }
+                },
+                Source::Bytes(path, bytes) => (path, bytes),
+            };
+            _ = results.send(task(labeler, path, bytes).await).await;
+        });
+        true
+    }
+}
+
+/// Work done on every file of `Labeler::many`.
+type Task<T> = fn(Labeler, PathBuf, Vec<u8>) -> Pin<Box<dyn Future<Output = Result<T>> + Send>>;
+
+enum Source {
+    File(PathBuf),
+    Bytes(PathBuf, Vec<u8>),
+}
@@ -0,0 +1,27 @@
+//! Labels audio as speech, music, noise or silence, either with
+//! janitor-service instances or with a model loaded into the process.
+//! [`Labeler`] is the entry point.
+
+pub mod archive;
+pub mod audio;
+pub mod balancer;
+pub mod batcher;
+pub mod bench;
+pub mod client;
+pub mod config;
+pub mod decoder;
+pub mod dedupe;
+pub mod evaluation;
+pub mod exec;
+pub mod flac;
+pub mod labeler;
+pub mod limits;
+pub mod overrides;
+pub mod playlist;
+pub mod processing;
+pub mod review;
+pub mod split;
+pub mod transfer;
+
+pub use labeler::{Labeler, LabelerOptions};
+pub use processing::{Backend, Label, Prediction, ProcessOptions, Record, Scores, Window};
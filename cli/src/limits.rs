@@ -0,0 +1,56 @@
+use anyhow::{Context, Result};
+use std::{num::NonZeroUsize, sync::Arc, thread::available_parallelism};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+pub fn cores() -> usize {
+    available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+/// Default number of files held in memory at once.
+pub fn default_open_files() -> usize {
+    cores() * 16
+}
+
+/// Default number of files decoded and turned into fbanks at once.
+pub fn default_workers() -> usize {
+    cores()
+}
+
+/// Default number of requests in flight to the services at once.
+pub fn default_requests() -> usize {
+    cores() * 4
+}
+
+/// Caps on the work of a run that is not bounded by the service client.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    files: Arc<Semaphore>,
+    workers: Arc<Semaphore>,
+}
+
+impl Limits {
+    pub fn new(files: usize, workers: usize) -> Limits {
+        Limits {
+            files: Arc::new(Semaphore::new(files.max(1))),
+            workers: Arc::new(Semaphore::new(workers.max(1))),
+        }
+    }
+
+    /// Held from opening a file until its record is ready.
+    pub async fn file(&self) -> Result<OwnedSemaphorePermit> {
+        self.files
+            .clone()
+            .acquire_owned()
+            .await
+            .with_context(|| "Failed to acquire file permit")
+    }
+
+    /// Held while a file is decoded and its fbanks are computed.
+    pub async fn worker(&self) -> Result<OwnedSemaphorePermit> {
+        self.workers
+            .clone()
+            .acquire_owned()
+            .await
+            .with_context(|| "Failed to acquire worker permit")
+    }
+}
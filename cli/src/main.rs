@@ -1,164 +1,1387 @@
-use anyhow::{Context, Error, Result};
+use anyhow::{bail, Context, Error, Result};
 use async_walkdir::{Filtering, WalkDir};
-use clap::{Parser, Subcommand};
+use clap::{
+    builder::RangedU64ValueParser, error::ErrorKind, ArgAction, CommandFactory, Parser, Subcommand,
+    ValueEnum,
+};
+use itertools::Itertools;
+use janitor::{
+    archive::{contents, extract, is_archive, split_member_path},
+    audio::{is_audio_file, Resampler},
+    balancer::Strategy,
+    bench::{bench, synthetic, BenchOptions, Sample, Stage, HEADER},
+    client::ClientOptions,
+    config::{Action, Condition, Config, Format, MultiLabel, Profile, Threshold},
+    dedupe::{cluster, redundant, similarity, Embedded},
+    evaluation::{best, load_truth, sweep, Evaluation, Outcome},
+    exec::{run, Execution, Template},
+    limits::{default_open_files, default_requests, default_workers},
+    overrides::{self, Overrides},
+    playlist::{PlaylistFormat, PlaylistOptions, PlaylistPaths, Playlists},
+    processing::{get_result_path, ResultPathOptions},
+    review,
+    split::{spans, split, write_manifest, Piece, SplitFormat, SplitOptions},
+    transfer, Backend, Label, Labeler, LabelerOptions, ProcessOptions, Record, Scores, Window,
+};
+use janitor_common::{
+    logging::{self, LogFormat},
+    token,
+};
+use parse_duration::parse;
+use serde_json::json;
 use spinoff::{spinners, Spinner};
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 use tokio::{
-    fs::{copy, rename},
+    fs::{create_dir_all, read, remove_file},
     sync::Semaphore,
-    task::JoinSet,
+    task::{spawn_blocking, JoinSet},
 };
-use tokio_stream::StreamExt;
-
-pub mod audio;
-use audio::is_audio_file;
+use tokio_stream::{once, Stream, StreamExt};
+use tracing::{error, info, level_filters::LevelFilter, warn};
 
-pub mod processing;
-use processing::{get_result_path, process, Label, ResultPathOptions};
-
-const MAX_OPEN_FILES: usize = 128;
-static PERMITS: Semaphore = Semaphore::const_new(MAX_OPEN_FILES);
+/// Warnings and errors unless `-v` or `-q` say otherwise.
+const LOG_LEVEL: LevelFilter = LevelFilter::WARN;
 
 #[derive(Parser)]
-#[command(about, long_about = None, version)]
+#[command(about, long_about = None, version, subcommand_negates_reqs = true)]
 struct Args {
     #[command(subcommand)]
     command: Option<Command>,
 
     #[arg(required = true)]
-    path: PathBuf,
+    path: Option<PathBuf>,
+
+    /// Config file to use instead of the project's janitor.toml
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Named profile of the config files to apply
+    #[arg(short, long)]
+    profile: Option<String>,
+
+    /// Service addresses as `host:port` or `http://`, `https://` and `unix://` URLs,
+    /// comma separated or repeated [default: 0.0.0.0:8000]
+    #[arg(short, long, value_delimiter = ',')]
+    address: Option<Vec<String>>,
+
+    /// How requests are spread over several service addresses [default: round-robin]
+    #[arg(long, value_enum)]
+    balance: Option<Strategy>,
+
+    /// Bearer token sent to the services
+    #[arg(long, env = "JANITOR_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// File holding the bearer token sent to the services
+    #[arg(long)]
+    token_file: Option<PathBuf>,
+
+    /// Extra PEM root certificate to trust, e.g. for a self-signed service
+    #[arg(long)]
+    ca_cert: Option<PathBuf>,
+
+    /// How long to wait for a connection to a service [default: 10s]
+    #[arg(long)]
+    connect_timeout: Option<String>,
+
+    /// How long to wait for a service to label a file [default: 60s]
+    #[arg(long)]
+    request_timeout: Option<String>,
+
+    /// How many times a request is retried on connection errors and 5xx responses [default: 3]
+    #[arg(long)]
+    retries: Option<u32>,
+
+    /// Pause before the first retry, doubled for every further one [default: 250ms]
+    #[arg(long)]
+    backoff: Option<String>,
+
+    /// How many fbanks are sent to a service in one request [default: 1]
+    #[arg(long)]
+    batch_size: Option<usize>,
+
+    /// How long a batch waits for more fbanks before it is sent [default: 20ms]
+    #[arg(long)]
+    batch_delay: Option<String>,
+
+    /// File of labels set with `override` or `review`, which win over the model's
+    /// [default: overrides.json in the user's data directory]
+    #[arg(long)]
+    overrides: Option<PathBuf>,
+
+    /// Only label files with one of these extensions, comma separated
+    #[arg(long, value_delimiter = ',')]
+    extensions: Option<Vec<String>>,
+
+    /// Score from which a label is reported next to the primary one, e.g. `music=0.3`,
+    /// comma separated [default: 0.5 for every label]
+    #[arg(long, value_delimiter = ',')]
+    threshold: Option<Vec<Threshold>>,
+
+    /// Only copy or move files labelled with at least this score
+    #[arg(long)]
+    min_score: Option<f32>,
+
+    /// Don't copy or move files matching any of these conditions, e.g. `duration<1`,
+    /// comma separated
+    #[arg(long, value_delimiter = ',')]
+    skip_if: Option<Vec<Condition>>,
+
+    /// Copy or move files matching any of these conditions, e.g. `clipping>0.001`,
+    /// to the review directory instead
+    #[arg(long, value_delimiter = ',')]
+    review_if: Option<Vec<Condition>>,
+
+    /// Also label audio inside zip and tar archives found in directories
+    #[arg(long, overrides_with = "no_archives")]
+    archives: bool,
+
+    /// Leave archives alone even if a config file sets `archives`
+    #[arg(long, overrides_with = "archives")]
+    no_archives: bool,
+
+    /// Write audio from archives to the label directories when copying or moving
+    #[arg(long, overrides_with = "no_extract")]
+    extract: bool,
+
+    /// Leave audio in archives even if a config file sets `extract`
+    #[arg(long, overrides_with = "extract")]
+    no_extract: bool,
+
+    /// Write a playlist per label into this directory, without moving any files
+    #[arg(long)]
+    playlist_dir: Option<PathBuf>,
+
+    /// Playlist formats to write, comma separated [default: m3u8]
+    #[arg(long, value_enum, value_delimiter = ',')]
+    playlist_format: Option<Vec<PlaylistFormat>>,
+
+    /// How playlists refer to files [default: relative]
+    #[arg(long, value_enum)]
+    playlist_paths: Option<PlaylistPaths>,
+
+    /// Put the score of the label in playlist entry titles
+    #[arg(long, overrides_with = "no_playlist_scores")]
+    playlist_scores: bool,
+
+    /// Leave scores out of playlist entry titles even if a config file sets
+    /// `playlist-scores`
+    #[arg(long, overrides_with = "playlist_scores")]
+    no_playlist_scores: bool,
+
+    /// How records are printed [default: text]
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+
+    /// How many files are held in memory at once [default: 16 per core]
+    #[arg(long)]
+    max_open_files: Option<usize>,
+
+    /// How many files are decoded and turned into fbanks at once [default: one per core]
+    #[arg(long)]
+    workers: Option<usize>,
+
+    /// How many requests are in flight to the services at once [default: 4 per core]
+    #[arg(long)]
+    max_requests: Option<usize>,
 
-    #[arg(short, long, default_value = "0.0.0.0:8000")]
-    address: String,
+    /// Label with this TorchScript model in-process instead of a service
+    #[cfg(feature = "local")]
+    #[arg(long)]
+    local_model: Option<PathBuf>,
+
+    /// Also label every channel of multichannel files independently
+    #[arg(long)]
+    per_channel: bool,
+
+    /// How audio is converted to the model's sample rate
+    #[arg(long, value_enum, default_value_t)]
+    resampler: Resampler,
+
+    /// Level in dBFS below which audio is trimmed, or the file reported as silent
+    #[arg(long, default_value_t = -60.0, allow_negative_numbers = true)]
+    silence_threshold: f32,
+
+    /// Label long files in windows of this length instead of only their start
+    /// [default for split: 1s]
+    #[arg(long)]
+    window: Option<String>,
+
+    /// Time between the starts of two windows, at most the window length
+    /// [default: the window length]
+    #[arg(long, requires = "window")]
+    hop: Option<String>,
+
+    /// Log more: info, debug and trace events with one, two and three
+    #[arg(short, long, action = ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Log less: only errors with one, nothing with two
+    #[arg(short, long, action = ArgAction::Count)]
+    quiet: u8,
+
+    /// How log events are written [default: human]
+    #[arg(long, value_enum)]
+    log_format: Option<LogFormat>,
+
+    /// Append log events to this file instead of standard error
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+}
+
+impl Args {
+    /// The settings given on the command line, which win over config files.
+    fn profile(&self) -> Profile {
+        let mut profile = Profile {
+            address: self.address.clone(),
+            balance: self.balance,
+            token: self.token.clone(),
+            token_file: self.token_file.clone(),
+            ca_cert: self.ca_cert.clone(),
+            connect_timeout: self.connect_timeout.clone(),
+            request_timeout: self.request_timeout.clone(),
+            retries: self.retries,
+            backoff: self.backoff.clone(),
+            batch_size: self.batch_size,
+            batch_delay: self.batch_delay.clone(),
+            overrides: self.overrides.clone(),
+            extensions: self.extensions.clone(),
+            thresholds: self.threshold.clone(),
+            min_score: self.min_score,
+            skip_if: self.skip_if.clone(),
+            review_if: self.review_if.clone(),
+            archives: flag(self.archives, self.no_archives),
+            extract: flag(self.extract, self.no_extract),
+            playlist_dir: self.playlist_dir.clone(),
+            playlist_formats: self.playlist_format.clone(),
+            playlist_paths: self.playlist_paths,
+            playlist_scores: flag(self.playlist_scores, self.no_playlist_scores),
+            format: self.format,
+            log_format: self.log_format,
+            log_file: self.log_file.clone(),
+            ..Profile::default()
+        };
+        if let Some(ref command) = self.command {
+            let (action, dirs) = match command {
+                Command::Copy(dirs) => (Some(Action::Copy), dirs),
+                Command::Move(dirs) => (Some(Action::Move), dirs),
+                Command::Exec(args) => (None, &args.dirs),
+                Command::Dedupe(_)
+                | Command::Review(_)
+                | Command::Override(_)
+                | Command::Evaluate(_)
+                | Command::Bench(_)
+                | Command::Split(_) => return profile,
+            };
+            profile.action = action;
+            profile.speech_dir = dirs.speech_dir.clone();
+            profile.music_dir = dirs.music_dir.clone();
+            profile.noise_dir = dirs.noise_dir.clone();
+            profile.silence_dir = dirs.silence_dir.clone();
+            profile.review_dir = dirs.review_dir.clone();
+            profile.multi_label = dirs.multi_label;
+            profile.combo_dir = dirs.combo_dir.clone();
+        }
+        profile
+    }
+}
+
+/// A `--<flag>` and `--no-<flag>` pair as a setting, unset when neither was given.
+fn flag(yes: bool, no: bool) -> Option<bool> {
+    match (yes, no) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct Dirs {
+    #[arg(short, long)]
+    speech_dir: Option<PathBuf>,
+
+    #[arg(short, long)]
+    music_dir: Option<PathBuf>,
+
+    #[arg(short, long)]
+    noise_dir: Option<PathBuf>,
+
+    #[arg(long)]
+    silence_dir: Option<PathBuf>,
+
+    /// Where files matching `--review-if` go
+    #[arg(long)]
+    review_dir: Option<PathBuf>,
+
+    /// Where files with more than one label go [default: primary]
+    #[arg(long, value_enum)]
+    multi_label: Option<MultiLabel>,
+
+    /// Where `--multi-label combo` creates a directory per combination of labels
+    #[arg(long)]
+    combo_dir: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ExecArgs {
+    /// Command to run for every labelled file, with `{path}`, `{label}`, `{score}` and
+    /// `{dest}` placeholders, e.g. `ffmpeg -i {path} {dest}.opus`
+    command: String,
+
+    /// How many commands run at once [default: one per core]
+    #[arg(short, long)]
+    jobs: Option<usize>,
+
+    #[command(flatten)]
+    dirs: Dirs,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct Dedupe {
+    /// Cosine similarity of embeddings from which two files count as duplicates
+    #[arg(long, default_value_t = 0.95)]
+    threshold: f32,
+
+    /// Delete every file of a group but the one of best quality
+    #[arg(long)]
+    keep_best: bool,
+
+    /// Move the other files of a group here instead of deleting them
+    #[arg(long, requires = "keep_best")]
+    duplicates_dir: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ReviewArgs {
+    /// Only review files labelled with less than this score
+    #[arg(long)]
+    max_score: Option<f32>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct EvaluateArgs {
+    /// CSV file with `path` and `label` columns, or JSON lines with those fields
+    truth: PathBuf,
+
+    /// Also try minimum scores from 0 to 1 and report the most accurate one
+    #[arg(long)]
+    sweep: bool,
+
+    /// Share of files the most accurate minimum score must still keep
+    #[arg(long, default_value_t = 0.9, requires = "sweep")]
+    min_coverage: f32,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+    /// Stages to time, comma separated [default: all]
+    #[arg(long, value_enum, value_delimiter = ',')]
+    stages: Option<Vec<Stage>>,
+
+    /// How many items are processed at once, comma separated
+    #[arg(long, value_delimiter = ',', default_value = "1,4,16", value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    concurrency: Vec<usize>,
+
+    /// How many fbanks are sent to a service in one request during inference,
+    /// comma separated
+    #[arg(long, value_delimiter = ',', default_value = "1,8,32", value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    batch_sizes: Vec<usize>,
+
+    /// How many items every stage processes at every level
+    #[arg(long, default_value_t = 64, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    iterations: usize,
+
+    /// How many synthetic files, or files found under the path, to use
+    #[arg(long, default_value_t = 8)]
+    files: usize,
+
+    /// Length of the synthetic files
+    #[arg(long, default_value = "30s")]
+    duration: String,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SplitArgs {
+    /// Label of the segments to cut out
+    #[arg(short, long, value_enum)]
+    label: Label,
+
+    /// Where segments and their manifest.csv are written
+    #[arg(short, long)]
+    dir: PathBuf,
+
+    /// Format of the segments
+    #[arg(long, value_enum, default_value_t)]
+    audio_format: SplitFormat,
+
+    /// Audio kept before and after every segment
+    #[arg(long, default_value = "250ms")]
+    padding: String,
+
+    /// Segments shorter than this, before padding, are left out
+    #[arg(long, default_value = "1s")]
+    min_length: String,
+
+    /// Segments this close to each other are joined into one
+    #[arg(long, default_value = "500ms")]
+    merge_gap: String,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct OverrideArgs {
+    #[command(subcommand)]
+    command: OverrideCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum OverrideCommand {
+    /// Always give these files a label instead of labelling them
+    Set {
+        #[arg(value_enum)]
+        label: Label,
+
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+
+        /// Match the files by path instead of by contents, which follow them
+        /// when they are moved
+        #[arg(long)]
+        by_path: bool,
+    },
+
+    /// Print every override
+    List,
+
+    /// Let the model label these files again
+    Remove {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Command {
     #[command()]
-    Copy {
-        #[arg(short, long)]
-        speech_dir: Option<PathBuf>,
+    Copy(Dirs),
 
-        #[arg(short, long)]
-        music_dir: Option<PathBuf>,
+    #[command()]
+    Move(Dirs),
 
-        #[arg(short, long)]
-        noise_dir: Option<PathBuf>,
-    },
+    /// Run a command for every labelled file, where `{dest}` is where copy
+    /// and move would put it
+    #[command()]
+    Exec(ExecArgs),
 
+    /// Group near-duplicate files by their model embeddings
     #[command()]
-    Move {
-        #[arg(short, long)]
-        speech_dir: Option<PathBuf>,
+    Dedupe(Dedupe),
 
-        #[arg(short, long)]
-        music_dir: Option<PathBuf>,
+    /// Go through labelled files in a terminal UI, least certain first, and
+    /// accept or correct their labels
+    #[command()]
+    Review(ReviewArgs),
 
-        #[arg(short, long)]
-        noise_dir: Option<PathBuf>,
-    },
+    /// Set, list or remove labels set by hand, which every command uses
+    /// instead of the model's
+    #[command()]
+    Override(OverrideArgs),
+
+    /// Label the files of a ground-truth list and report how well the labels
+    /// match, without a path
+    #[command()]
+    Evaluate(EvaluateArgs),
+
+    /// Time every stage of labelling at several levels of concurrency and batch
+    /// sizes, on the files under the path or on synthetic audio without one
+    #[command()]
+    Bench(BenchArgs),
+
+    /// Write the stretches of files with one label as separate audio files,
+    /// using windowed labels
+    #[command()]
+    Split(SplitArgs),
+}
+
+/// Copies or moves a file through a partial file renamed into place, so an
+/// interrupted run never leaves a half-written one. Moves to other
+/// filesystems are verified before the original is deleted.
+async fn perform(action: Action, path: &Path, destination: &Path) -> Result<()> {
+    if let Some(dir) = destination.parent() {
+        create_dir_all(dir).await?;
+    }
+    let path = path.to_path_buf();
+    let destination = destination.to_path_buf();
+    spawn_blocking(move || match action {
+        Action::Copy => transfer::copy(&path, &destination),
+        Action::Move => transfer::move_file(&path, &destination),
+    })
+    .await?
 }
 
-impl Command {
-    async fn perform(&self, path: &Path, label: &Label, options: &ResultPathOptions) -> Result<()> {
-        if let Some(dir) = get_result_path(path, label, options) {
-            match self {
-                Command::Copy { .. } => {
-                    let _ = copy(path, &dir).await?;
-                    return Ok(());
+async fn backend(args: &Args, settings: &Profile) -> Result<Backend> {
+    #[cfg(feature = "local")]
+    if let Some(ref path) = args.local_model {
+        return Backend::local(path);
+    }
+    let addresses = settings
+        .address
+        .clone()
+        .unwrap_or_else(|| vec!["0.0.0.0:8000".to_string()]);
+    let options = ClientOptions {
+        connect_timeout: parse(settings.connect_timeout.as_deref().unwrap_or("10s"))?,
+        request_timeout: parse(settings.request_timeout.as_deref().unwrap_or("60s"))?,
+        retries: settings.retries.unwrap_or(3),
+        backoff: parse(settings.backoff.as_deref().unwrap_or("250ms"))?,
+        max_requests: args.max_requests.unwrap_or_else(default_requests),
+        token: token::load(settings.token.clone(), settings.token_file.as_deref())?,
+        ca_cert: settings.ca_cert.clone(),
+    };
+    let backend = Backend::service(&addresses, settings.balance.unwrap_or_default(), options)?;
+    let delay = parse(settings.batch_delay.as_deref().unwrap_or("20ms"))?;
+    Ok(backend
+        .batched(settings.batch_size.unwrap_or(1), delay)
+        .await)
+}
+
+fn window(args: &Args) -> Result<Option<Window>> {
+    let splitting = matches!(args.command, Some(Command::Split(_)));
+    let length = match args.window {
+        Some(ref length) => parse(length)?,
+        None if splitting => Duration::from_secs(1),
+        None => return Ok(None),
+    };
+    let hop = match args.hop {
+        Some(ref hop) => parse(hop)?,
+        None => length,
+    };
+    if hop > length {
+        bail!("The hop must not be longer than the window");
+    }
+    Ok(Some(Window::new(length, hop)))
+}
+
+/// Prints per-endpoint statistics when requests were spread over several services.
+fn summarize(backend: &Backend) {
+    if let Some(client) = backend.client() {
+        if client.balancer().stats().len() > 1 {
+            print!("{}", client.balancer());
+        }
+    }
+}
+
+fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    path.extension().is_some_and(|extension| {
+        extensions
+            .iter()
+            .any(|allowed| extension.eq_ignore_ascii_case(allowed.trim_start_matches('.')))
+    })
+}
+
+fn show(record: &Record, format: Format) -> Result<String> {
+    Ok(match format {
+        Format::Text => record.to_string(),
+        Format::Json => {
+            serde_json::to_string(record).with_context(|| "Failed to serialize record")?
+        }
+    })
+}
+
+/// The configured thresholds, with 0.5 for labels without one.
+fn thresholds(settings: &Profile) -> Scores {
+    let mut scores = ProcessOptions::default().thresholds;
+    for threshold in settings.thresholds.iter().flatten() {
+        match threshold.label {
+            Label::Speech => scores.speech = threshold.score,
+            Label::Music => scores.music = threshold.score,
+            Label::Noise => scores.noise = threshold.score,
+            Label::Silence => {}
+        }
+    }
+    scores
+}
+
+/// Archive members to write to their label directories once labelling is
+/// done, by archive and member name.
+type Extractions = HashMap<PathBuf, HashMap<String, Vec<PathBuf>>>;
+
+/// The review directory for files matching a review condition, or else the
+/// directories of the labels that are set, as the multi-label policy says.
+fn destinations(record: &Record, settings: &Profile) -> Vec<PathBuf> {
+    let Some(name) = record.path.file_name() else {
+        return Vec::new();
+    };
+    let review = settings
+        .review_if
+        .iter()
+        .flatten()
+        .any(|condition| condition.matches(&record.metrics));
+    if let (true, Some(dir)) = (review, &settings.review_dir) {
+        return vec![dir.join(name)];
+    }
+    let options = ResultPathOptions {
+        speech_dir: settings.speech_dir.clone(),
+        music_dir: settings.music_dir.clone(),
+        noise_dir: settings.noise_dir.clone(),
+        silence_dir: settings.silence_dir.clone(),
+    };
+    let labels = match settings.multi_label.unwrap_or_default() {
+        _ if record.labels.len() < 2 => &record.labels[..],
+        MultiLabel::Primary => &record.labels[..1],
+        MultiLabel::Combo => {
+            let Some(ref dir) = settings.combo_dir else {
+                return Vec::new();
+            };
+            let combination = record
+                .labels
+                .iter()
+                .sorted()
+                .map(|label| format!("{:?}", label).to_lowercase())
+                .join("+");
+            return vec![dir.join(combination).join(name)];
+        }
+        MultiLabel::All => &record.labels[..],
+    };
+    labels
+        .iter()
+        .filter_map(|label| get_result_path(&record.path, label, &options))
+        .collect()
+}
+
+/// Whether a file is labelled with less than the minimum score or matches a
+/// skip condition. Silence carries no score and is never below it.
+fn skipped(record: &Record, settings: &Profile) -> bool {
+    if let Some(min_score) = settings.min_score {
+        if record.label != Label::Silence && !record.overridden && record.score() < min_score {
+            return true;
+        }
+    }
+    settings
+        .skip_if
+        .iter()
+        .flatten()
+        .any(|condition| condition.matches(&record.metrics))
+}
+
+/// Copies or moves a labelled file as configured, unless its score is below
+/// the minimum or it matches a skip condition. Silence carries no score and
+/// is always acted on. Files inside archives are only queued for extraction,
+/// and archives are never changed.
+///
+/// Returns where the file can be found afterwards, if anywhere outside an
+/// archive.
+async fn act(
+    record: &Record,
+    settings: &Profile,
+    extractions: &mut Extractions,
+) -> Result<Option<PathBuf>> {
+    let member = split_member_path(&record.path);
+    let unchanged = Ok(member.is_none().then(|| record.path.clone()));
+    let Some(action) = settings.action else {
+        return unchanged;
+    };
+    if skipped(record, settings) {
+        return unchanged;
+    }
+    let destinations = destinations(record, settings);
+    let Some((last, rest)) = destinations.split_last() else {
+        return unchanged;
+    };
+    let location = destinations[0].clone();
+    if let Some((archive, name)) = member {
+        if !settings.extract.unwrap_or(false) {
+            return unchanged;
+        }
+        extractions
+            .entry(archive)
+            .or_default()
+            .insert(name, destinations);
+        return Ok(Some(location));
+    }
+    // A file going to several places is copied to all but the last one.
+    for destination in rest {
+        perform(Action::Copy, &record.path, destination)
+            .await
+            .with_context(|| format!("failed to perform command {:?}", Action::Copy))?;
+    }
+    perform(action, &record.path, last)
+        .await
+        .with_context(|| format!("failed to perform command {:?}", action))?;
+    Ok(Some(location))
+}
+
+fn playlists(settings: &Profile) -> Option<Playlists> {
+    let dir = settings.playlist_dir.clone()?;
+    Some(Playlists::new(PlaylistOptions {
+        dir,
+        formats: settings
+            .playlist_formats
+            .clone()
+            .unwrap_or_else(|| vec![PlaylistFormat::M3u8]),
+        paths: settings.playlist_paths.unwrap_or_default(),
+        scores: settings.playlist_scores.unwrap_or(false),
+    }))
+}
+
+async fn extract_all(extractions: Extractions) -> Result<()> {
+    for (archive, destinations) in extractions {
+        spawn_blocking(move || extract(&archive, &destinations)).await??;
+    }
+    Ok(())
+}
+
+/// Audio files, and archives when enabled, under `dir`.
+fn walk(dir: &Path, settings: &Profile) -> impl Stream<Item = PathBuf> + Send + 'static {
+    let extensions = Arc::new(settings.extensions.clone());
+    let archives = settings.archives.unwrap_or(false);
+    let entries = WalkDir::new(dir).filter(move |entry| {
+        let extensions = extensions.clone();
+        async move {
+            let path = entry.path();
+            if archives && is_archive(&path) {
+                return Filtering::Continue;
+            }
+            let allowed = match *extensions {
+                Some(ref extensions) => has_extension(&path, extensions),
+                None => true,
+            };
+            if allowed && is_audio_file(entry).await.unwrap_or(false) {
+                Filtering::Continue
+            } else {
+                Filtering::Ignore
+            }
+        }
+    });
+    entries.filter_map(|entry| match entry {
+        Ok(entry) => Some(entry.path()),
+        Err(e) => {
+            warn!("Failed to read directory entry: {}", e);
+            None
+        }
+    })
+}
+
+/// One line per group of duplicates, best quality first.
+fn show_group(items: &[Embedded], group: &[usize], format: Format) -> Result<String> {
+    let best = &items[group[0]];
+    let members = group
+        .iter()
+        .map(|&index| {
+            let item = &items[index];
+            (item, similarity(&best.vector, &item.vector))
+        })
+        .collect::<Vec<_>>();
+    if format == Format::Json {
+        let members = members
+            .iter()
+            .map(|(item, similarity)| {
+                json!({
+                    "path": item.path,
+                    "quality": item.quality,
+                    "similarity": similarity,
+                })
+            })
+            .collect::<Vec<_>>();
+        return serde_json::to_string(&json!({ "members": members }))
+            .with_context(|| "Failed to serialize group");
+    }
+    let mut text = format!("{} duplicates:", group.len());
+    for (item, similarity) in members {
+        let quality = item.quality;
+        text += &format!(
+            "\n  {:?} ({}, {} Hz, {} channels, {} bytes, similarity {:.3})",
+            item.path,
+            if quality.lossless {
+                "lossless"
+            } else {
+                "lossy"
+            },
+            quality.sample_rate,
+            quality.channels,
+            quality.size,
+            similarity
+        );
+    }
+    Ok(text)
+}
+
+/// Deletes a duplicate, or moves it to the same place under `dir` as it
+/// has under `root`, so that duplicates with the same name don't collide.
+/// Files inside archives are left where they are, and files already in
+/// `dir` are never replaced.
+async fn discard(path: &Path, root: &Path, dir: Option<&Path>) -> Result<()> {
+    if split_member_path(path).is_some() {
+        warn!("Leaving {:?} in its archive", path);
+        return Ok(());
+    }
+    match dir {
+        Some(dir) => {
+            let relative = path
+                .strip_prefix(root)
+                .ok()
+                .filter(|relative| !relative.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new(path.file_name().unwrap_or_default()));
+            let (path, destination) = (path.to_path_buf(), dir.join(relative));
+            if let Some(parent) = destination.parent() {
+                create_dir_all(parent)
+                    .await
+                    .with_context(|| format!("Failed to create {:?}", parent))?;
+            }
+            spawn_blocking(move || transfer::move_new(&path, &destination)).await?
+        }
+        None => remove_file(path)
+            .await
+            .with_context(|| format!("Failed to delete {:?}", path)),
+    }
+}
+
+async fn dedupe(
+    labeler: &Labeler,
+    root: &Path,
+    paths: impl Stream<Item = PathBuf> + Send + 'static,
+    dedupe: &Dedupe,
+    format: Format,
+) -> Result<()> {
+    let mut embedded = labeler.embed_many(paths);
+    let mut items = Vec::new();
+    while let Some(result) = embedded.next().await {
+        match result {
+            Ok(item) => items.push(item),
+            Err(e) => error!("{:#}", e),
+        }
+    }
+    for group in cluster(&items, dedupe.threshold) {
+        println!("{}", show_group(&items, &group, format)?);
+        if dedupe.keep_best {
+            let discarded = redundant(&items, &group, dedupe.threshold);
+            for &index in &group[1..] {
+                if !discarded.contains(&index) {
+                    info!(
+                        "Keeping {:?}, which is less than {} similar to {:?}",
+                        items[index].path, dedupe.threshold, items[group[0]].path
+                    );
+                }
+            }
+            for index in discarded {
+                let dir = dedupe.duplicates_dir.as_deref();
+                discard(&items[index].path, root, dir).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Labels everything under `paths` and hands the records to the review UI,
+/// saving the decisions as overrides when it is closed.
+async fn review(
+    labeler: &Labeler,
+    paths: impl Stream<Item = PathBuf> + Send + 'static,
+    options: &ReviewArgs,
+    overrides: &Path,
+) -> Result<()> {
+    let mut records = labeler.label_many(paths);
+    let mut pending = Vec::new();
+    while let Some(result) = records.next().await {
+        match result {
+            Ok(record) => pending.push(record),
+            Err(e) => error!("{:#}", e),
+        }
+    }
+    if let Some(max_score) = options.max_score {
+        pending.retain(|record| record.label != Label::Silence && record.score() < max_score);
+    }
+    let mut loaded = Overrides::load(overrides)?;
+    let (loaded, result) = spawn_blocking(move || {
+        let result = review::review(pending, &mut loaded);
+        (loaded, result)
+    })
+    .await?;
+    // Whatever was decided before a failure is kept.
+    loaded.save(overrides)?;
+    result
+}
+
+/// How many minimum scores `--sweep` tries.
+const SWEEP_STEPS: usize = 20;
+
+/// Labels the files listed in the ground truth and compares the labels.
+async fn evaluate(labeler: &Labeler, options: &EvaluateArgs, format: Format) -> Result<()> {
+    let truth = load_truth(&options.truth)?;
+    let paths = truth
+        .iter()
+        .map(|(path, _)| path.clone())
+        .collect::<Vec<_>>();
+    let expected = truth.into_iter().collect::<HashMap<_, _>>();
+    let mut records = labeler.label_many(tokio_stream::iter(paths));
+    let mut outcomes = Vec::new();
+    let mut failed = 0;
+    while let Some(result) = records.next().await {
+        match result {
+            Ok(record) => {
+                if let Some(&label) = expected.get(&record.path) {
+                    outcomes.push(Outcome::new(&record, label));
+                }
+            }
+            Err(e) => {
+                error!("{:#}", e);
+                failed += 1;
+            }
+        }
+    }
+    let evaluation = Evaluation::new(&outcomes);
+    let points = options.sweep.then(|| sweep(&outcomes, SWEEP_STEPS));
+    let best = points
+        .as_deref()
+        .and_then(|points| best(points, options.min_coverage));
+
+    if format == Format::Json {
+        let report = json!({
+            "evaluation": evaluation,
+            "failed": failed,
+            "sweep": points,
+            "best": best,
+        });
+        println!("{}", report);
+        return Ok(());
+    }
+    print!("{}", evaluation);
+    if failed > 0 {
+        println!("\n{} files failed to label", failed);
+    }
+    if let Some(points) = points {
+        println!(
+            "\n{:>9} {:>8} {:>8} {:>8}",
+            "min score", "coverage", "accuracy", "macro f1"
+        );
+        for point in points {
+            println!(
+                "{:>9.2} {:>8.3} {:>8.3} {:>8.3}",
+                point.threshold, point.coverage, point.accuracy, point.macro_f1
+            );
+        }
+        match best {
+            Some(best) => println!(
+                "\nBest: --min-score {:.2} (accuracy {:.3}, coverage {:.3})",
+                best.threshold, best.accuracy, best.coverage
+            ),
+            None => println!(
+                "\nNo minimum score keeps {:.0}% of the files",
+                options.min_coverage * 100.0
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Up to `options.files` audio files under `path`, or synthetic files.
+async fn samples(
+    path: Option<&Path>,
+    options: &BenchArgs,
+    settings: &Profile,
+) -> Result<Vec<Sample>> {
+    let Some(path) = path else {
+        let seconds = parse(&options.duration)?.as_secs_f64();
+        return spawn_blocking({
+            let files = options.files;
+            move || {
+                (0..files)
+                    .map(|seed| synthetic(seconds, seed as u64))
+                    .collect()
+            }
+        })
+        .await
+        .with_context(|| "Failed to generate audio");
+    };
+    let paths = if path.is_file() {
+        vec![path.to_path_buf()]
+    } else {
+        walk(path, settings)
+            .filter(|path| !is_archive(path))
+            .take(options.files)
+            .collect::<Vec<_>>()
+            .await
+    };
+    if paths.is_empty() {
+        bail!("No audio files found in {:?}", path);
+    }
+    let mut samples = Vec::with_capacity(paths.len());
+    for path in paths {
+        samples.push(Sample {
+            bytes: read(&path)
+                .await
+                .with_context(|| format!("Failed to read {:?}", path))?,
+            extension: path
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase()),
+        });
+    }
+    Ok(samples)
+}
+
+async fn run_bench(
+    backend: &Backend,
+    path: Option<&Path>,
+    options: &BenchArgs,
+    settings: &Profile,
+    resampler: Resampler,
+) -> Result<()> {
+    let format = settings.format.unwrap_or_default();
+    let samples = samples(path, options, settings).await?;
+    let bench_options = BenchOptions {
+        stages: options
+            .stages
+            .clone()
+            .unwrap_or_else(|| Stage::value_variants().to_vec()),
+        concurrency: options.concurrency.clone(),
+        batch_sizes: options.batch_sizes.clone(),
+        batch_delay: parse(settings.batch_delay.as_deref().unwrap_or("20ms"))?,
+        iterations: options.iterations,
+        resampler,
+    };
+    if format == Format::Text {
+        println!("{}", HEADER);
+    }
+    let measurements = bench(samples, Some(backend), &bench_options, |measurement| {
+        if format == Format::Text {
+            println!("{}", measurement);
+        }
+    })
+    .await?;
+    if format == Format::Json {
+        let json =
+            serde_json::to_string(&measurements).with_context(|| "Failed to serialize results")?;
+        println!("{}", json);
+    }
+    Ok(())
+}
+
+/// Labels everything under `paths` and cuts out the segments with the
+/// chosen label, writing a manifest of all of them at the end.
+async fn split_all(
+    labeler: &Labeler,
+    root: &Path,
+    paths: impl Stream<Item = PathBuf> + Send + 'static,
+    args: &SplitArgs,
+    format: Format,
+) -> Result<()> {
+    let options = Arc::new(SplitOptions {
+        label: args.label,
+        dir: args.dir.clone(),
+        root: root.to_path_buf(),
+        format: args.audio_format,
+        padding: parse(&args.padding)?.as_secs_f64(),
+        min_length: parse(&args.min_length)?.as_secs_f64(),
+        merge_gap: parse(&args.merge_gap)?.as_secs_f64(),
+    });
+    let mut records = labeler.label_many(paths);
+    let mut pieces: Vec<Piece> = Vec::new();
+    while let Some(result) = records.next().await {
+        let record = result.with_context(|| "Failed to label a file")?;
+        println!("{}", show(&record, format)?);
+        let spans = spans(&record, &options);
+        let options = options.clone();
+        let written = spawn_blocking(move || split(&record, &spans, &options)).await??;
+        for piece in &written {
+            if format == Format::Text {
+                println!(
+                    "  {:.2} - {:.2} s ({:.2}) -> {:?}",
+                    piece.start, piece.end, piece.score, piece.path
+                );
+            }
+        }
+        pieces.extend(written);
+    }
+    write_manifest(&options.dir, &pieces)
+}
+
+/// Prints a file whose command is done, and whether it failed.
+fn finish(mut record: Record, result: Result<Execution>, format: Format) -> Result<bool> {
+    let failed = match result {
+        Ok(execution) => {
+            let failed = !execution.success();
+            record.exec = Some(execution);
+            failed
+        }
+        Err(e) => {
+            error!("{:#}", e);
+            true
+        }
+    };
+    println!("{}", show(&record, format)?);
+    Ok(failed)
+}
+
+/// Labels everything under `paths` and runs the command for every file
+/// that isn't skipped, at most `jobs` at once. Files whose command fails
+/// fail the run once all are done.
+async fn exec_all(
+    labeler: &Labeler,
+    paths: impl Stream<Item = PathBuf> + Send + 'static,
+    args: &ExecArgs,
+    settings: &Profile,
+    format: Format,
+) -> Result<()> {
+    let template = Template::parse(&args.command)?;
+    let jobs = Arc::new(Semaphore::new(args.jobs.unwrap_or_else(default_workers)));
+    let mut records = labeler.label_many(paths);
+    let mut running = JoinSet::new();
+    let mut failed = 0;
+    while let Some(result) = records.next().await {
+        let record = result.with_context(|| "Failed to label a file")?;
+        if skipped(&record, settings) {
+            println!("{}", show(&record, format)?);
+            continue;
+        }
+        if split_member_path(&record.path).is_some() {
+            warn!(
+                "Not running the command for {:?} in an archive",
+                record.path
+            );
+            println!("{}", show(&record, format)?);
+            continue;
+        }
+        let destination = destinations(&record, settings).into_iter().next();
+        if destination.is_none() && template.uses_destination() {
+            error!("No directory for {:?} to fill in {{dest}}", record.path);
+            println!("{}", show(&record, format)?);
+            failed += 1;
+            continue;
+        }
+        let command = template.render(&record, destination.as_deref());
+        let permit = jobs.clone().acquire_owned().await?;
+        running.spawn(async move {
+            let result = run(command).await;
+            drop(permit);
+            (record, result)
+        });
+        while let Some(done) = running.try_join_next() {
+            let (record, result) = done?;
+            failed += finish(record, result, format)? as usize;
+        }
+    }
+    while let Some(done) = running.join_next().await {
+        let (record, result) = done?;
+        failed += finish(record, result, format)? as usize;
+    }
+    if failed > 0 {
+        bail!("The command failed for {} files", failed);
+    }
+    Ok(())
+}
+
+fn show_overrides(overrides: &Overrides, format: Format) -> Result<String> {
+    if format == Format::Json {
+        return serde_json::to_string(overrides).with_context(|| "Failed to serialize overrides");
+    }
+    let paths = overrides
+        .paths
+        .iter()
+        .map(|(path, label)| format!("{:?}: {:?}", path, label));
+    let hashes = overrides
+        .hashes
+        .iter()
+        .map(|(hash, label)| format!("sha256:{}: {:?}", hash, label));
+    Ok(paths.chain(hashes).collect::<Vec<_>>().join("\n"))
+}
+
+/// Applies an `override` command to the overrides in `file`.
+fn edit_overrides(command: &OverrideCommand, file: &Path, format: Format) -> Result<()> {
+    let mut overrides = Overrides::load(file)?;
+    match command {
+        OverrideCommand::Set {
+            label,
+            paths,
+            by_path,
+        } => {
+            for path in paths {
+                if *by_path {
+                    overrides.set_path(path, *label);
+                } else {
+                    // An override by path would win over the new one.
+                    overrides.remove(path, None);
+                    overrides.set_hash(&contents(path)?, *label);
                 }
-                Command::Move { .. } => {
-                    rename(path, &dir).await?;
-                    return Ok(());
+            }
+        }
+        OverrideCommand::List => {
+            if !overrides.is_empty() {
+                println!("{}", show_overrides(&overrides, format)?);
+            }
+            return Ok(());
+        }
+        OverrideCommand::Remove { paths } => {
+            for path in paths {
+                let bytes = contents(path).ok();
+                if !overrides.remove(path, bytes.as_deref()) {
+                    warn!("No override for {:?}", path);
                 }
             }
         }
-        Ok(())
     }
+    overrides.save(file)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     let args = Args::parse();
-    let url = format!("http:/{}/", args.address);
-    let options = match args.command {
-        Some(Command::Copy {
-            ref speech_dir,
-            ref music_dir,
-            ref noise_dir,
-        }) => Some(ResultPathOptions {
-            speech_dir: speech_dir.clone(),
-            music_dir: music_dir.clone(),
-            noise_dir: noise_dir.clone(),
-        }),
-        Some(Command::Move {
-            ref speech_dir,
-            ref music_dir,
-            ref noise_dir,
-        }) => Some(ResultPathOptions {
-            speech_dir: speech_dir.clone(),
-            music_dir: music_dir.clone(),
-            noise_dir: noise_dir.clone(),
-        }),
-        _ => None,
+    let config = Config::discover(args.config.as_deref())?;
+    let settings = args.profile().or(config.profile(args.profile.as_deref())?);
+    logging::init(
+        logging::level(LOG_LEVEL, args.verbose, args.quiet),
+        settings.log_format.unwrap_or_default(),
+        settings.log_file.as_deref(),
+    )?;
+    let format = settings.format.unwrap_or_default();
+    if settings.multi_label == Some(MultiLabel::Combo) && settings.combo_dir.is_none() {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--multi-label combo needs a --combo-dir",
+            )
+            .exit();
+    }
+    let overrides_file = settings
+        .overrides
+        .clone()
+        .unwrap_or_else(overrides::default_path);
+    if let Some(Command::Override(ref command)) = args.command {
+        return edit_overrides(&command.command, &overrides_file, format);
+    }
+    let evaluating = matches!(args.command, Some(Command::Evaluate(_)));
+    // Evaluation measures the model, not labels set by hand.
+    let overrides = if evaluating {
+        Overrides::default()
+    } else {
+        Overrides::load(&overrides_file)?
+    };
+    let backend = backend(&args, &settings).await?;
+    let options = LabelerOptions {
+        process: ProcessOptions {
+            per_channel: args.per_channel,
+            resampler: args.resampler,
+            silence_threshold: args.silence_threshold,
+            window: window(&args)?,
+            overrides: (!overrides.is_empty()).then(|| Arc::new(overrides)),
+            thresholds: thresholds(&settings),
+        },
+        max_open_files: args.max_open_files.unwrap_or_else(default_open_files),
+        workers: args.workers.unwrap_or_else(default_workers),
     };
+    let labeler = Labeler::new(backend, options);
 
-    let mut spinner = Spinner::new(spinners::Line, "Loading...", None);
+    if let Some(Command::Bench(ref options)) = args.command {
+        let path = args.path.as_deref();
+        run_bench(labeler.backend(), path, options, &settings, args.resampler).await?;
+        summarize(labeler.backend());
+        return Ok(());
+    }
 
-    if args.path.is_file() {
-        let permit = PERMITS
-            .acquire()
-            .await
-            .with_context(|| "Failed to acquire permit")?;
-        spinner.update_text(format!("Labelling {:?}", args.path));
-        let (path, label) = process(args.path.clone(), url.clone(), permit)
-            .await
-            .with_context(|| format!("Failed to process {:?}", args.path))?;
-        if let Some(ref command) = args.command {
-            command
-                .perform(&path, &label, &options.unwrap())
-                .await
-                .with_context(|| format!("failed to perform command {:?}", command))?;
+    if let Some(Command::Evaluate(ref options)) = args.command {
+        evaluate(&labeler, options, format).await?;
+        summarize(labeler.backend());
+        return Ok(());
+    }
+
+    let Some(path) = args.path.clone() else {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "A path to label is required",
+            )
+            .exit();
+    };
+
+    if let Some(Command::Dedupe(ref options)) = args.command {
+        if path.is_file() {
+            dedupe(&labeler, &path, once(path.clone()), options, format).await?;
         } else {
-            spinner.stop_with_message(&format!("{:?}: {:?}", path, label));
+            dedupe(&labeler, &path, walk(&path, &settings), options, format).await?;
         }
+        summarize(labeler.backend());
         return Ok(());
     }
 
-    let mut entries = WalkDir::new(args.path).filter(|entry| async move {
-        if is_audio_file(entry).await.unwrap_or(false) {
-            Filtering::Continue
+    if let Some(Command::Exec(ref options)) = args.command {
+        if path.is_file() {
+            exec_all(&labeler, once(path), options, &settings, format).await?;
         } else {
-            Filtering::Ignore
+            exec_all(&labeler, walk(&path, &settings), options, &settings, format).await?;
         }
-    });
-    let mut jobs = JoinSet::new();
-    loop {
-        match entries.next().await {
-            Some(Ok(entry)) => {
-                let permit = PERMITS
-                    .acquire()
-                    .await
-                    .with_context(|| "Failed to acquire permit")?;
-                let path = entry.path();
-                spinner.update_text(format!("Labelling {:?}", path));
-                let future = process(path, url.clone(), permit);
-                jobs.spawn(future);
+        summarize(labeler.backend());
+        return Ok(());
+    }
+
+    if let Some(Command::Split(ref options)) = args.command {
+        if path.is_file() {
+            split_all(&labeler, &path, once(path.clone()), options, format).await?;
+        } else {
+            split_all(&labeler, &path, walk(&path, &settings), options, format).await?;
+        }
+        summarize(labeler.backend());
+        return Ok(());
+    }
+
+    if let Some(Command::Review(ref options)) = args.command {
+        if path.is_file() {
+            review(&labeler, once(path), options, &overrides_file).await?;
+        } else {
+            review(&labeler, walk(&path, &settings), options, &overrides_file).await?;
+        }
+        return Ok(());
+    }
+
+    let mut extractions = Extractions::new();
+    if path.is_file() && !is_archive(&path) {
+        // The spinner would get in the way of log events.
+        let mut spinner = (args.verbose == 0)
+            .then(|| Spinner::new(spinners::Line, format!("Labelling {:?}", path), None));
+        let record = labeler
+            .label_file(&path)
+            .await
+            .with_context(|| format!("Failed to process {:?}", path))?;
+        let location = if settings.action.is_some() {
+            if let Some(ref mut spinner) = spinner {
+                spinner.stop();
+            }
+            act(&record, &settings, &mut extractions).await?
+        } else {
+            let shown = show(&record, format)?;
+            match spinner {
+                Some(ref mut spinner) => spinner.stop_with_message(&shown),
+                None => println!("{}", shown),
             }
-            Some(Err(e)) => return Err(e.into()),
-            None => break,
+            Some(record.path.clone())
         };
+        if let (Some(mut playlists), Some(location)) = (playlists(&settings), location) {
+            playlists.add(&record, &location)?;
+            playlists.write()?;
+        }
+        summarize(labeler.backend());
+        return Ok(());
     }
-    spinner.stop();
-    while let Some(result) = jobs.join_next().await {
-        let (path, label) = result?.with_context(|| "Failed to label a file")?;
-        println!("{:?}: {:?}", path, label);
-        if let Some(ref command) = args.command {
-            command
-                .perform(&path, &label, &options.clone().unwrap())
-                .await
-                .with_context(|| format!("failed to perform command {:?}", command))?;
+
+    let mut records = if path.is_file() {
+        labeler.label_many(once(path.clone()))
+    } else {
+        labeler.label_many(walk(&path, &settings))
+    };
+    let mut playlists = playlists(&settings);
+    while let Some(result) = records.next().await {
+        let record = result.with_context(|| "Failed to label a file")?;
+        println!("{}", show(&record, format)?);
+        let location = act(&record, &settings, &mut extractions).await?;
+        if let (Some(playlists), Some(location)) = (playlists.as_mut(), location) {
+            playlists.add(&record, &location)?;
         }
     }
+    extract_all(extractions).await?;
+    if let Some(playlists) = playlists {
+        playlists.write()?;
+    }
+    summarize(labeler.backend());
     Ok(())
 }
@@ -0,0 +1,98 @@
+use crate::{processing::Label, transfer};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs::{create_dir_all, read_to_string},
+    io::ErrorKind,
+    path::{absolute, Path, PathBuf},
+};
+
+/// Where overrides are kept unless configured otherwise.
+pub fn default_path() -> PathBuf {
+    dirs::data_dir()
+        .map(|dir| dir.join("janitor").join("overrides.json"))
+        .unwrap_or_else(|| PathBuf::from("janitor-overrides.json"))
+}
+
+/// The SHA-256 of a file's contents, in hex.
+pub fn hash(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(64);
+    for byte in Sha256::digest(bytes) {
+        _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+fn key(path: &Path) -> PathBuf {
+    absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Labels set by hand, which files get instead of the model's. Files are
+/// matched by absolute path, or by contents to follow them when they move.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Overrides {
+    #[serde(default)]
+    pub paths: BTreeMap<PathBuf, Label>,
+    #[serde(default)]
+    pub hashes: BTreeMap<String, Label>,
+}
+
+impl Overrides {
+    /// No overrides if the file does not exist yet.
+    pub fn load(path: &Path) -> Result<Overrides> {
+        let text = match read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Overrides::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+        serde_json::from_str(&text).with_context(|| format!("Failed to parse {:?}", path))
+    }
+
+    /// Replaces the file at once, so an interrupted save keeps the old one.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        }
+        let text = serde_json::to_string_pretty(self)?;
+        transfer::write(path, text.as_bytes())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.hashes.is_empty()
+    }
+
+    pub fn set_path(&mut self, path: &Path, label: Label) {
+        self.paths.insert(key(path), label);
+    }
+
+    pub fn set_hash(&mut self, bytes: &[u8], label: Label) {
+        self.hashes.insert(hash(bytes), label);
+    }
+
+    /// Drops the overrides of the file at `path` with contents `bytes`,
+    /// returning whether there were any.
+    pub fn remove(&mut self, path: &Path, bytes: Option<&[u8]>) -> bool {
+        let by_path = self.paths.remove(&key(path)).is_some();
+        let by_hash = bytes.is_some_and(|bytes| self.hashes.remove(&hash(bytes)).is_some());
+        by_path || by_hash
+    }
+
+    pub fn by_path(&self, path: &Path) -> Option<Label> {
+        self.paths.get(&key(path)).copied()
+    }
+
+    /// The override of the file at `path` with contents `bytes`, where one
+    /// set by path wins. Contents are only hashed if needed.
+    pub fn find(&self, path: &Path, bytes: &[u8]) -> Option<Label> {
+        self.by_path(path).or_else(|| {
+            if self.hashes.is_empty() {
+                None
+            } else {
+                self.hashes.get(&hash(bytes)).copied()
+            }
+        })
+    }
+}
@@ -0,0 +1,202 @@
+use crate::processing::{Label, Record};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs::{canonicalize, create_dir_all, write},
+    path::{absolute, Component, Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum PlaylistFormat {
+    /// Extended M3U in UTF-8
+    M3u8,
+    /// XML Shareable Playlist Format
+    Xspf,
+}
+
+impl PlaylistFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u8 => "m3u8",
+            PlaylistFormat::Xspf => "xspf",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum PlaylistPaths {
+    /// Relative to the playlist, so the directory can be moved as a whole
+    #[default]
+    Relative,
+    /// Absolute paths
+    Absolute,
+}
+
+#[derive(Debug, Clone)]
+pub struct PlaylistOptions {
+    /// Where `<label>.<format>` playlists are written.
+    pub dir: PathBuf,
+    pub formats: Vec<PlaylistFormat>,
+    pub paths: PlaylistPaths,
+    /// Puts the score of the label in every entry's title.
+    pub scores: bool,
+}
+
+struct Entry {
+    path: PathBuf,
+    title: String,
+    duration: f64,
+}
+
+/// Collects labelled files during a run and writes one playlist per label
+/// and format at the end.
+pub struct Playlists {
+    options: PlaylistOptions,
+    entries: BTreeMap<String, Vec<Entry>>,
+}
+
+fn label_name(label: Label) -> String {
+    format!("{:?}", label).to_lowercase()
+}
+
+/// Resolves symlinks when the file exists, so that relative paths are taken
+/// between real locations.
+fn resolve(path: &Path) -> Result<PathBuf> {
+    canonicalize(path)
+        .or_else(|_| absolute(path))
+        .with_context(|| format!("Failed to resolve {:?}", path))
+}
+
+/// The absolute `path` relative to the absolute directory `base`.
+fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let path = path.components().collect::<Vec<_>>();
+    let base = base.components().collect::<Vec<_>>();
+    let common = path
+        .iter()
+        .zip(&base)
+        .take_while(|(path, base)| path == base)
+        .count();
+    let mut relative = PathBuf::new();
+    for _ in common..base.len() {
+        relative.push(Component::ParentDir);
+    }
+    relative.extend(&path[common..]);
+    relative
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Percent-encodes a path for use as a URI, keeping its separators.
+fn encode_uri(path: &Path) -> String {
+    let mut uri = String::new();
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            _ = write!(uri, "%{:02X}", byte);
+        }
+    }
+    uri
+}
+
+impl Playlists {
+    pub fn new(options: PlaylistOptions) -> Playlists {
+        Playlists {
+            options,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the file of `record`, found at `path` after any copy or move.
+    pub fn add(&mut self, record: &Record, path: &Path) -> Result<()> {
+        let path = resolve(path)?;
+        let mut title = path
+            .file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy()
+            .to_string();
+        if self.options.scores && record.label != Label::Silence && !record.overridden {
+            title = format!("{} ({:.2})", title, record.score());
+        }
+        self.entries
+            .entry(label_name(record.label))
+            .or_default()
+            .push(Entry {
+                path,
+                title,
+                duration: record.metrics.duration,
+            });
+        Ok(())
+    }
+
+    pub fn write(&self) -> Result<()> {
+        let dir = &self.options.dir;
+        create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        let base = resolve(dir)?;
+        let location = |entry: &Entry| match self.options.paths {
+            PlaylistPaths::Relative => relative_to(&entry.path, &base),
+            PlaylistPaths::Absolute => entry.path.clone(),
+        };
+        for (label, entries) in &self.entries {
+            for &format in &self.options.formats {
+                let text = match format {
+                    PlaylistFormat::M3u8 => m3u8(entries, location),
+                    PlaylistFormat::Xspf => xspf(label, entries, location),
+                };
+                let path = dir.join(label).with_extension(format.extension());
+                write(&path, text).with_context(|| format!("Failed to write {:?}", path))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn m3u8(entries: &[Entry], location: impl Fn(&Entry) -> PathBuf) -> String {
+    let mut text = "#EXTM3U\n".to_string();
+    for entry in entries {
+        _ = writeln!(
+            text,
+            "#EXTINF:{},{}\n{}",
+            entry.duration.round() as i64,
+            entry.title,
+            location(entry).display()
+        );
+    }
+    text
+}
+
+fn xspf(label: &str, entries: &[Entry], location: impl Fn(&Entry) -> PathBuf) -> String {
+    let mut text = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".to_string();
+    text += "<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n";
+    _ = writeln!(text, "  <title>{}</title>", escape_xml(label));
+    text += "  <trackList>\n";
+    for entry in entries {
+        let path = location(entry);
+        let location = if path.is_absolute() {
+            format!("file://{}", encode_uri(&path))
+        } else {
+            encode_uri(&path)
+        };
+        text += "    <track>\n";
+        _ = writeln!(text, "      <location>{}</location>", escape_xml(&location));
+        _ = writeln!(text, "      <title>{}</title>", escape_xml(&entry.title));
+        _ = writeln!(
+            text,
+            "      <duration>{}</duration>",
+            (entry.duration * 1000.0).round() as u64
+        );
+        text += "    </track>\n";
+    }
+    text += "  </trackList>\n</playlist>\n";
+    text
+}
@@ -1,81 +1,649 @@
-use crate::audio::{create_fbank, extract_audio, resample, NUM_MEL_BINS, SAMPLE_RATE};
-use anyhow::{Context, Result};
+use crate::{
+    audio::{
+        create_fbank, extract_audio, measure, resample, trim_silence, Extracted, Fbank, Metrics,
+        Resampler, FRAMES_PER_SECOND, NUM_FRAMES, NUM_MEL_BINS, SAMPLE_RATE,
+    },
+    balancer::{Balancer, Strategy},
+    batcher::Batcher,
+    client::{url, ClientOptions, ServiceClient},
+    decoder::SourceFormat,
+    exec::Execution,
+    limits::Limits,
+    overrides::Overrides,
+};
+#[cfg(feature = "local")]
+use anyhow::anyhow;
+use anyhow::{bail, Context, Result};
 use byte_slice_cast::AsByteSlice;
-use reqwest::Client;
+use clap::ValueEnum;
+use fon::{chan::Ch32, Audio};
+use itertools::Itertools;
+#[cfg(feature = "local")]
+use janitor_model::{fit, from_fbank, normalize, Model};
 use safetensors::{serialize, tensor::TensorView, Dtype};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "local")]
+use std::sync::Mutex;
 use std::{
+    cmp::Reverse,
     collections::HashMap,
+    fmt,
+    iter::once,
     path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use tokio::{
-    fs::File,
-    io::AsyncReadExt,
-    sync::SemaphorePermit,
-    task::{block_in_place, spawn_blocking},
-};
+use tokio::{fs::File, io::AsyncReadExt, sync::OwnedSemaphorePermit, task::spawn_blocking};
+use tracing::{debug, info};
 
-#[derive(Debug, Clone, Copy, Deserialize)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize, ValueEnum,
+)]
 pub enum Label {
     Speech,
     Music,
     Noise,
+    /// Silent files are never sent to the service.
+    Silence,
 }
 
-async fn label<'a>(tensor: &'a TensorView<'a>, url: String) -> Result<Label> {
-    let bytes = block_in_place(|| -> Result<Vec<u8>> {
-        let tensors = HashMap::from([("fbank", tensor)]);
-        let bytes = serialize(tensors, &None).with_context(|| "Failed to serialize tensor")?;
-        Ok(bytes)
-    })?;
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+pub struct Scores {
+    pub speech: f32,
+    pub music: f32,
+    pub noise: f32,
+}
 
-    let response = Client::new()
-        .post(url.clone())
-        .body(bytes)
-        .send()
-        .await
-        .with_context(|| format!("Failed to send bytes to {}", url))?;
-    let text = response
-        .text()
-        .await
-        .with_context(|| "Failed to extract response body")?;
-    let label = serde_json::from_str(&text).with_context(|| "Failed to deserialize output")?;
-    Ok(label)
+impl Scores {
+    /// The score of `label`, where silence has none.
+    pub fn of(&self, label: Label) -> f32 {
+        match label {
+            Label::Speech => self.speech,
+            Label::Music => self.music,
+            Label::Noise => self.noise,
+            Label::Silence => 0.0,
+        }
+    }
+
+    /// The highest scored label of those scored at least their threshold,
+    /// or noise when none is, as the model does.
+    pub fn primary(&self, thresholds: &Scores) -> Label {
+        [Label::Speech, Label::Music, Label::Noise]
+            .into_iter()
+            .filter(|&label| self.of(label) >= thresholds.of(label))
+            .max_by(|&a, &b| self.of(a).total_cmp(&self.of(b)))
+            .unwrap_or(Label::Noise)
+    }
+
+    /// `primary` followed by every other label scored at least its
+    /// threshold, highest first.
+    pub fn labels(&self, primary: Label, thresholds: &Scores) -> Vec<Label> {
+        if primary == Label::Silence {
+            return vec![primary];
+        }
+        let others = [Label::Speech, Label::Music, Label::Noise]
+            .into_iter()
+            .filter(|&label| label != primary && self.of(label) >= thresholds.of(label))
+            .sorted_by(|&a, &b| self.of(b).total_cmp(&self.of(a)));
+        once(primary).chain(others).collect()
+    }
 }
 
-pub async fn process(
-    path: PathBuf,
-    url: String,
-    _permit: SemaphorePermit<'_>,
-) -> Result<(PathBuf, Label)> {
-    let name = path
-        .file_name()
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct Prediction {
+    pub label: Label,
+    pub scores: Scores,
+}
+
+impl Prediction {
+    /// Labels `scores` with `thresholds`, whatever label the backend chose
+    /// with its own.
+    pub fn new(scores: Scores, thresholds: &Scores) -> Prediction {
+        Prediction {
+            label: scores.primary(thresholds),
+            scores,
+        }
+    }
+
+    fn silence() -> Prediction {
+        Prediction {
+            label: Label::Silence,
+            scores: Scores::default(),
+        }
+    }
+
+    /// The score of the predicted label.
+    pub fn score(&self) -> f32 {
+        self.scores.of(self.label)
+    }
+}
+
+/// A stretch of a file labelled on its own, in seconds from its start.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Segment {
+    pub start: f64,
+    pub end: f64,
+    pub prediction: Prediction,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Record {
+    pub path: PathBuf,
+    pub label: Label,
+    /// `label` followed by every other label scored at least its threshold.
+    pub labels: Vec<Label>,
+    pub scores: Scores,
+    /// Predictions for every channel on its own, in channel order.
+    pub channels: Vec<Prediction>,
+    /// Predictions for every window of the downmix, when windowing.
+    pub windows: Vec<Segment>,
+    pub metrics: Metrics,
+    pub format: SourceFormat,
+    /// Whether the label was set by hand rather than by the model.
+    pub overridden: bool,
+    /// The command run for the file by `exec`, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exec: Option<Execution>,
+}
+
+impl Record {
+    /// The score of the predicted label.
+    pub fn score(&self) -> f32 {
+        self.scores.of(self.label)
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let labels = self
+            .labels
+            .iter()
+            .map(|label| format!("{:?}", label))
+            .join(" + ");
+        write!(f, "{:?}: {}", self.path, labels)?;
+        if self.overridden {
+            write!(f, " (overridden)")?;
+        }
+        write!(f, " ({})", self.format)?;
+        if let Some(ref exec) = self.exec {
+            match exec.status {
+                Some(code) => write!(f, " (exit {})", code)?,
+                None => write!(f, " (killed)")?,
+            }
+        }
+        if !self.channels.is_empty() {
+            let channels = self
+                .channels
+                .iter()
+                .enumerate()
+                .map(|(channel, prediction)| {
+                    format!(
+                        "{}: {:?} {:.2}",
+                        channel,
+                        prediction.label,
+                        prediction.score()
+                    )
+                })
+                .join(", ");
+            write!(f, " [{}]", channels)?;
+        }
+        Ok(())
+    }
+}
+
+/// Labels long audio window by window instead of only its start.
+#[derive(Debug, Clone, Copy)]
+pub struct Window {
+    /// Frames per window, at most the model's `NUM_FRAMES`.
+    pub length: usize,
+    /// Frames from the start of one window to the next, at most `length`
+    /// so that no audio is skipped.
+    pub hop: usize,
+}
+
+impl Window {
+    pub fn new(length: Duration, hop: Duration) -> Window {
+        let frames = |duration: Duration| {
+            ((duration.as_secs_f64() * FRAMES_PER_SECOND as f64).round() as usize).max(1)
+        };
+        let length = frames(length).min(NUM_FRAMES);
+        Window {
+            length,
+            hop: frames(hop).min(length),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProcessOptions {
+    /// Label every channel of multichannel files independently as well.
+    pub per_channel: bool,
+    pub resampler: Resampler,
+    /// Level in dBFS below which audio counts as silence.
+    pub silence_threshold: f32,
+    pub window: Option<Window>,
+    /// Labels set by hand, used instead of labelling matching files.
+    pub overrides: Option<Arc<Overrides>>,
+    /// Scores from which labels are reported next to the primary one.
+    pub thresholds: Scores,
+}
+
+impl Default for ProcessOptions {
+    fn default() -> ProcessOptions {
+        ProcessOptions {
+            per_channel: false,
+            resampler: Resampler::default(),
+            silence_threshold: -60.0,
+            window: None,
+            overrides: None,
+            thresholds: Scores {
+                speech: 0.5,
+                music: 0.5,
+                noise: 0.5,
+            },
+        }
+    }
+}
+
+/// The fbank of audio with its silent ends trimmed, and where it starts in
+/// the untrimmed audio, in seconds.
+pub(crate) struct Features {
+    pub fbank: Fbank,
+    pub offset: f64,
+}
+
+/// Returns `None` for audio that is silent throughout.
+pub(crate) fn to_features(
+    audio: Audio<Ch32, 1>,
+    options: &ProcessOptions,
+) -> Result<Option<Features>> {
+    let sample_rate = audio.sample_rate().get();
+    let Some((mut audio, start)) = trim_silence(audio, options.silence_threshold) else {
+        return Ok(None);
+    };
+    resample(&mut audio, SAMPLE_RATE, options.resampler);
+    let fbank = create_fbank(&mut audio).with_context(|| "Failed to create filter bank")?;
+    Ok(Some(Features {
+        fbank,
+        offset: start as f64 / sample_rate as f64,
+    }))
+}
+
+/// Where fbanks are sent to be labelled.
+#[derive(Clone)]
+pub enum Backend {
+    /// janitor-service instances behind a client-side balancer.
+    Service(Arc<ServiceClient>),
+    /// The same, with fbanks of several files sent in one request.
+    Batched(Batcher),
+    /// A model loaded into this process.
+    #[cfg(feature = "local")]
+    Local(Arc<Mutex<Model>>),
+}
+
+impl Backend {
+    /// janitor-service instances at `addresses`, given as `host:port` or URLs.
+    pub fn service<S: AsRef<str>>(
+        addresses: &[S],
+        strategy: Strategy,
+        options: ClientOptions,
+    ) -> Result<Backend> {
+        let urls = addresses
+            .iter()
+            .map(|address| url(address.as_ref()))
+            .collect::<Result<_>>()?;
+        let client = ServiceClient::new(Balancer::new(urls, strategy), options)?;
+        Ok(Backend::Service(Arc::new(client)))
+    }
+
+    /// Sends up to `size` fbanks per request, waiting at most `delay` for a
+    /// batch to fill. Other backends are returned unchanged.
+    pub async fn batched(self, size: usize, delay: Duration) -> Backend {
+        match self {
+            Backend::Service(client) if size > 1 => {
+                Backend::Batched(Batcher::new(client, size, delay).await)
+            }
+            backend => backend,
+        }
+    }
+
+    /// The same backend sending one fbank per request.
+    pub fn unbatched(self) -> Backend {
+        match self {
+            Backend::Batched(batcher) => Backend::Service(batcher.into_client()),
+            backend => backend,
+        }
+    }
+
+    /// Loads a TorchScript model into this process.
+    #[cfg(feature = "local")]
+    pub fn local(path: &Path) -> Result<Backend> {
+        let model = Model::new(path).with_context(|| format!("Failed to load model {:?}", path))?;
+        Ok(Backend::Local(Arc::new(Mutex::new(model))))
+    }
+
+    pub fn client(&self) -> Option<&ServiceClient> {
+        match self {
+            Backend::Service(client) => Some(client),
+            Backend::Batched(batcher) => Some(batcher.client()),
+            #[cfg(feature = "local")]
+            Backend::Local(_) => None,
+        }
+    }
+}
+
+#[cfg(feature = "local")]
+impl From<janitor_model::Prediction> for Prediction {
+    fn from(prediction: janitor_model::Prediction) -> Prediction {
+        Prediction {
+            label: match prediction.label {
+                janitor_model::Label::Speech => Label::Speech,
+                janitor_model::Label::Music => Label::Music,
+                janitor_model::Label::Noise => Label::Noise,
+            },
+            scores: Scores {
+                speech: prediction.scores.speech,
+                music: prediction.scores.music,
+                noise: prediction.scores.noise,
+            },
+        }
+    }
+}
+
+/// The safetensors file holding `fbank`, as sent to the service.
+pub(crate) fn serialize_fbank(fbank: &Fbank) -> Result<Vec<u8>> {
+    let size = vec![fbank.len(), NUM_MEL_BINS];
+    let tensor = TensorView::new(Dtype::F32, size, fbank.as_byte_slice())
+        .with_context(|| "Failed to create tensor from fbank")?;
+    let tensors = HashMap::from([("fbank", &tensor)]);
+    serialize(tensors, &None).with_context(|| "Failed to serialize tensor")
+}
+
+async fn label_remote(fbank: Fbank, client: &ServiceClient) -> Result<Prediction> {
+    let bytes = spawn_blocking(move || serialize_fbank(&fbank)).await??;
+
+    let reply = client.post(bytes).await?;
+    if !reply.status.is_success() {
+        bail!("Service responded with {}: {}", reply.status, reply.body);
+    }
+    let prediction =
+        serde_json::from_str(&reply.body).with_context(|| "Failed to deserialize output")?;
+    Ok(prediction)
+}
+
+#[cfg(feature = "local")]
+fn label_local(fbank: &Fbank, model: &Mutex<Model>) -> Result<Prediction> {
+    let mut tensor = from_fbank(fbank.as_flattened())?;
+    fit(&mut tensor)?;
+    normalize(&mut tensor)?;
+    let model = model.lock().map_err(|_| anyhow!("Model lock poisoned"))?;
+    let prediction = model.label_one(&tensor)?;
+    Ok(prediction.into())
+}
+
+pub(crate) async fn label(fbank: Fbank, backend: &Backend) -> Result<Prediction> {
+    match backend {
+        Backend::Service(client) => label_remote(fbank, client).await,
+        Backend::Batched(batcher) => batcher.label(fbank).await,
+        #[cfg(feature = "local")]
+        Backend::Local(model) => {
+            let model = model.clone();
+            spawn_blocking(move || label_local(&fbank, &model)).await?
+        }
+    }
+}
+
+/// Labels the first `NUM_FRAMES` frames, or else every window, combining
+/// them by averaging their scores and taking the label most windows got.
+async fn label_features(
+    features: Option<Features>,
+    backend: &Backend,
+    window: Option<Window>,
+    thresholds: &Scores,
+) -> Result<(Prediction, Vec<Segment>)> {
+    let Some(Features { fbank, offset }) = features else {
+        return Ok((Prediction::silence(), Vec::new()));
+    };
+    let Some(window) = window else {
+        let length = fbank.len().min(NUM_FRAMES);
+        let prediction = label(fbank[..length].into(), backend).await?;
+        return Ok((Prediction::new(prediction.scores, thresholds), Vec::new()));
+    };
+
+    let seconds = |frame: usize| offset + frame as f64 / FRAMES_PER_SECOND as f64;
+    let mut segments = Vec::new();
+    let mut start = 0;
+    while start < fbank.len() {
+        let end = (start + window.length).min(fbank.len());
+        let prediction = label(fbank[start..end].into(), backend).await?;
+        segments.push(Segment {
+            start: seconds(start),
+            end: seconds(end),
+            prediction: Prediction::new(prediction.scores, thresholds),
+        });
+        if end == fbank.len() {
+            break;
+        }
+        start += window.hop;
+    }
+
+    let count = segments.len() as f32;
+    let mean = |score: fn(&Scores) -> f32| {
+        segments
+            .iter()
+            .map(|segment| score(&segment.prediction.scores))
+            .sum::<f32>()
+            / count
+    };
+    let scores = Scores {
+        speech: mean(|scores| scores.speech),
+        music: mean(|scores| scores.music),
+        noise: mean(|scores| scores.noise),
+    };
+    let votes = segments
+        .iter()
+        .map(|segment| segment.prediction.label)
+        .counts();
+    let label = segments
+        .iter()
+        .map(|segment| segment.prediction.label)
+        .min_by_key(|label| Reverse(votes[label]))
+        .unwrap_or(Label::Silence);
+    Ok((Prediction { label, scores }, segments))
+}
+
+pub(crate) fn file_name(path: &Path) -> String {
+    path.file_name()
         .unwrap_or(path.as_os_str())
         .to_string_lossy()
-        .to_string();
-    let mut file = File::open(&path)
+        .to_string()
+}
+
+/// Labels the downmix and the separate channels of decoded audio, unless
+/// `overridden` gives the label already.
+async fn label_audio(
+    path: PathBuf,
+    extracted: Extracted,
+    overridden: Option<Label>,
+    backend: &Backend,
+    options: &ProcessOptions,
+    limits: &Limits,
+) -> Result<Record> {
+    if let Some(label) = overridden {
+        info!(path = %path.display(), ?label, "Labelled file by override");
+        return Ok(Record {
+            path,
+            label,
+            labels: vec![label],
+            scores: Scores::default(),
+            channels: Vec::new(),
+            windows: Vec::new(),
+            metrics: extracted.metrics,
+            format: extracted.format,
+            overridden: true,
+            exec: None,
+        });
+    }
+    let Extracted {
+        audio,
+        channels,
+        metrics,
+        format,
+    } = extracted;
+    let name = file_name(&path);
+    let worker = limits.worker().await?;
+    let started = Instant::now();
+    let thread_options = options.clone();
+    let (features, channels) = spawn_blocking(
+        move || -> Result<(Option<Features>, Vec<Option<Features>>)> {
+            let features = to_features(audio, &thread_options)?;
+            let channels = channels
+                .into_iter()
+                .map(|audio| to_features(audio, &thread_options))
+                .collect::<Result<Vec<_>>>()?;
+            Ok((features, channels))
+        },
+    )
+    .await?
+    .with_context(|| format!("Failed to decode {}", name))?;
+    drop(worker);
+    debug!(path = %path.display(), elapsed = ?started.elapsed(), "Computed filter banks");
+
+    let started = Instant::now();
+    let (prediction, windows) =
+        label_features(features, backend, options.window, &options.thresholds)
+            .await
+            .with_context(|| format!("Failed to label {}", name))?;
+    let mut predictions = Vec::with_capacity(channels.len());
+    for (channel, features) in channels.into_iter().enumerate() {
+        let (prediction, _) =
+            label_features(features, backend, options.window, &options.thresholds)
+                .await
+                .with_context(|| format!("Failed to label channel {} of {}", channel, name))?;
+        predictions.push(prediction);
+    }
+    debug!(
+        path = %path.display(),
+        windows = windows.len(),
+        channels = predictions.len(),
+        elapsed = ?started.elapsed(),
+        "Ran inference"
+    );
+    info!(
+        path = %path.display(),
+        label = ?prediction.label,
+        score = %format_args!("{:.3}", prediction.scores.of(prediction.label)),
+        "Labelled file"
+    );
+    Ok(Record {
+        path,
+        label: prediction.label,
+        labels: prediction
+            .scores
+            .labels(prediction.label, &options.thresholds),
+        scores: prediction.scores,
+        channels: predictions,
+        windows,
+        metrics,
+        format,
+        overridden: false,
+        exec: None,
+    })
+}
+
+/// Labels an encoded file held in memory. `path` names the record, and its
+/// extension hints at the container format.
+pub async fn process_bytes(
+    path: PathBuf,
+    buffer: Vec<u8>,
+    backend: &Backend,
+    options: &ProcessOptions,
+    limits: &Limits,
+) -> Result<Record> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    let per_channel = options.per_channel;
+    let overrides = options.overrides.clone();
+    let thread_path = path.clone();
+    let worker = limits.worker().await?;
+    let started = Instant::now();
+    let (extracted, overridden) = spawn_blocking(move || -> Result<_> {
+        let overridden = overrides.and_then(|overrides| overrides.find(&thread_path, &buffer));
+        let extracted = extract_audio(
+            buffer,
+            extension.as_deref(),
+            per_channel && overridden.is_none(),
+        )
+        .with_context(|| "Failed to extract audio")?;
+        Ok((extracted, overridden))
+    })
+    .await?
+    .with_context(|| format!("Failed to decode {}", file_name(&path)))?;
+    drop(worker);
+    debug!(
+        path = %path.display(),
+        codec = %extracted.format.codec,
+        duration = extracted.metrics.duration,
+        elapsed = ?started.elapsed(),
+        "Decoded audio"
+    );
+    label_audio(path, extracted, overridden, backend, options, limits).await
+}
+
+/// Labels mono samples between -1 and 1. `name` only names the record.
+pub async fn process_samples(
+    name: PathBuf,
+    samples: Vec<f32>,
+    sample_rate: u32,
+    backend: &Backend,
+    options: &ProcessOptions,
+    limits: &Limits,
+) -> Result<Record> {
+    let extracted = Extracted {
+        metrics: measure(&samples, 1, sample_rate, &samples),
+        audio: Audio::with_f32_buffer(sample_rate, samples),
+        channels: Vec::new(),
+        format: SourceFormat {
+            codec: "raw".to_string(),
+            sample_format: "f32".to_string(),
+        },
+    };
+    let overridden = options
+        .overrides
+        .as_ref()
+        .and_then(|overrides| overrides.by_path(&name));
+    label_audio(name, extracted, overridden, backend, options, limits).await
+}
+
+pub(crate) async fn read_file(path: &Path) -> Result<Vec<u8>> {
+    let name = file_name(path);
+    let started = Instant::now();
+    let mut file = File::open(path)
         .await
         .with_context(|| format!("Failed to open {}", name))?;
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer)
         .await
         .with_context(|| format!("Failed to read file: {}", name))?;
+    debug!(
+        path = %path.display(),
+        bytes = buffer.len(),
+        elapsed = ?started.elapsed(),
+        "Read file"
+    );
+    Ok(buffer)
+}
 
-    let fbank = spawn_blocking(move || -> Result<Box<[[f32; NUM_MEL_BINS]]>> {
-        let mut audio = extract_audio(buffer).with_context(|| "Failed to extract audio")?;
-        resample(&mut audio, SAMPLE_RATE);
-        let fbank = create_fbank(&mut audio).with_context(|| "Failed to create filter bank")?;
-        Ok(fbank)
-    })
-    .await??;
-    let size = vec![fbank.len(), NUM_MEL_BINS];
-    let tensor = TensorView::new(Dtype::F32, size, fbank.as_byte_slice())
-        .with_context(|| "Failed to create tensor from fbank")?;
-
-    let label = label(&tensor, url)
-        .await
-        .with_context(|| format!("Failed to label {}", name))?;
-    Ok((path, label))
+pub async fn process(
+    path: PathBuf,
+    backend: Backend,
+    options: ProcessOptions,
+    limits: Limits,
+    _permit: OwnedSemaphorePermit,
+) -> Result<Record> {
+    let buffer = read_file(&path).await?;
+    process_bytes(path, buffer, &backend, &options, &limits).await
 }
 
 #[derive(Clone)]
@@ -83,6 +651,7 @@ pub struct ResultPathOptions {
     pub speech_dir: Option<PathBuf>,
     pub music_dir: Option<PathBuf>,
     pub noise_dir: Option<PathBuf>,
+    pub silence_dir: Option<PathBuf>,
 }
 
 pub fn get_result_path(path: &Path, label: &Label, options: &ResultPathOptions) -> Option<PathBuf> {
@@ -108,12 +677,15 @@ pub fn get_result_path(path: &Path, label: &Label, options: &ResultPathOptions)
                 return None;
             }
         }
+        Label::Silence => {
+            if let Some(ref dir) = options.silence_dir {
+                dir.clone()
+            } else {
+                return None;
+            }
+        }
     };
-    let name = path
-        .file_name()
-        .unwrap_or(path.as_os_str())
-        .to_string_lossy()
-        .to_string();
+    let name = file_name(path);
     dir.push(name);
     Some(dir)
 }
@@ -0,0 +1,280 @@
+use crate::{
+    archive::contents,
+    decoder::decode,
+    overrides::Overrides,
+    processing::{Label, Record},
+};
+use anyhow::{anyhow, Context, Result};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Modifier, Style, Stylize},
+    text::Line,
+    widgets::{Block, Paragraph, Row, Table, TableState},
+    DefaultTerminal, Frame,
+};
+use rodio::{buffer::SamplesBuffer, OutputStream, OutputStreamHandle, Sink};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// How unsure the model is of a record, from 0 to 1. Silence and labels
+/// set by hand are certain.
+pub fn uncertainty(record: &Record) -> f32 {
+    if record.overridden || record.label == Label::Silence {
+        0.0
+    } else {
+        1.0 - record.score()
+    }
+}
+
+/// Plays files on the default output device, opened on first use so that
+/// reviewing still works without one.
+#[derive(Default)]
+struct Player {
+    output: Option<(OutputStream, OutputStreamHandle)>,
+    sink: Option<Sink>,
+}
+
+impl Player {
+    /// Plays `path` from `start` to `end` seconds, or to its end.
+    fn play(&mut self, path: &Path, start: f64, end: Option<f64>) -> Result<()> {
+        self.stop();
+        let bytes = contents(path)?;
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        let decoded = decode(bytes, extension.as_deref())?;
+        let channels = decoded.channels.max(1);
+        let at = |seconds: f64| {
+            let frame = (seconds * decoded.sample_rate as f64) as usize;
+            (frame * channels).min(decoded.samples.len())
+        };
+        let samples = decoded.samples[at(start)..end.map_or(decoded.samples.len(), at)].to_vec();
+
+        if self.output.is_none() {
+            let output = OutputStream::try_default()
+                .map_err(|e| anyhow!("Failed to open audio output: {}", e))?;
+            self.output = Some(output);
+        }
+        let (_, handle) = self.output.as_ref().expect("output was just opened");
+        let sink = Sink::try_new(handle).map_err(|e| anyhow!("Failed to play: {}", e))?;
+        sink.append(SamplesBuffer::new(
+            channels as u16,
+            decoded.sample_rate,
+            samples,
+        ));
+        self.sink = Some(sink);
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if let Some(sink) = self.sink.take() {
+            sink.stop();
+        }
+    }
+}
+
+const HELP: &str = "↑↓ file  ←→ segment  space play  enter play segment  s stop  \
+                    a accept  1 speech  2 music  3 noise  4 silence  u undo  q quit";
+
+struct Review<'a> {
+    records: Vec<Record>,
+    overrides: &'a mut Overrides,
+    /// Labels chosen in this session, or `None` where one was undone.
+    decisions: HashMap<PathBuf, Option<Label>>,
+    file: TableState,
+    segment: TableState,
+    player: Player,
+    status: String,
+}
+
+impl Review<'_> {
+    fn selected(&self) -> Option<&Record> {
+        self.records.get(self.file.selected()?)
+    }
+
+    fn select_file(&mut self, index: usize) {
+        self.file
+            .select(Some(index.min(self.records.len().saturating_sub(1))));
+        let segments = self.selected().map_or(0, |record| record.windows.len());
+        self.segment.select((segments > 0).then_some(0));
+    }
+
+    fn decision(&self, record: &Record) -> Option<Label> {
+        match self.decisions.get(&record.path) {
+            Some(&decision) => decision,
+            None => record.overridden.then_some(record.label),
+        }
+    }
+
+    /// Overrides the label of the selected file by its contents, so that the
+    /// decision follows it when it is moved, or drops its overrides.
+    fn decide(&mut self, label: Option<Label>) {
+        let Some(record) = self.selected() else {
+            return;
+        };
+        let path = record.path.clone();
+        let bytes = match contents(&path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                self.status = format!("{:#}", e);
+                return;
+            }
+        };
+        self.overrides.remove(&path, Some(&bytes));
+        if let Some(label) = label {
+            self.overrides.set_hash(&bytes, label);
+        }
+        self.decisions.insert(path, label);
+        let next = self.file.selected().unwrap_or(0) + 1;
+        if label.is_some() && next < self.records.len() {
+            self.select_file(next);
+        }
+    }
+
+    fn play(&mut self, segment: bool) {
+        let Some(record) = self.selected() else {
+            return;
+        };
+        let path = record.path.clone();
+        let window = self
+            .segment
+            .selected()
+            .and_then(|index| record.windows.get(index))
+            .filter(|_| segment)
+            .map(|window| (window.start, Some(window.end)));
+        let (start, end) = window.unwrap_or((0.0, None));
+        self.status = match self.player.play(&path, start, end) {
+            Ok(()) => format!("Playing {:?} from {:.1} s", path, start),
+            Err(e) => format!("{:#}", e),
+        };
+    }
+
+    /// Handles a key, returning `false` to quit.
+    fn key(&mut self, code: KeyCode) -> bool {
+        let file = self.file.selected().unwrap_or(0);
+        let segment = self.segment.selected().unwrap_or(0);
+        let segments = self.selected().map_or(0, |record| record.windows.len());
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Up | KeyCode::Char('k') => self.select_file(file.saturating_sub(1)),
+            KeyCode::Down | KeyCode::Char('j') => self.select_file(file + 1),
+            KeyCode::Left | KeyCode::Char('h') if segments > 0 => {
+                self.segment.select(Some(segment.saturating_sub(1)))
+            }
+            KeyCode::Right | KeyCode::Char('l') if segments > 0 => {
+                self.segment.select(Some((segment + 1).min(segments - 1)))
+            }
+            KeyCode::Char(' ') => self.play(false),
+            KeyCode::Enter => self.play(true),
+            KeyCode::Char('s') => self.player.stop(),
+            KeyCode::Char('a') => {
+                let label = self.selected().map(|record| record.label);
+                self.decide(label);
+            }
+            KeyCode::Char('1') => self.decide(Some(Label::Speech)),
+            KeyCode::Char('2') => self.decide(Some(Label::Music)),
+            KeyCode::Char('3') => self.decide(Some(Label::Noise)),
+            KeyCode::Char('4') => self.decide(Some(Label::Silence)),
+            KeyCode::Char('u') => self.decide(None),
+            _ => {}
+        }
+        true
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [files, segments, status] = Layout::vertical([
+            Constraint::Fill(3),
+            Constraint::Fill(1),
+            Constraint::Length(2),
+        ])
+        .areas(frame.area());
+
+        let rows = self.records.iter().map(|record| {
+            let decision = self
+                .decision(record)
+                .map(|label| format!("{:?}", label))
+                .unwrap_or_default();
+            Row::new([
+                format!("{:.2}", record.score()),
+                format!("{:?}", record.label),
+                decision,
+                record.path.display().to_string(),
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(6),
+                Constraint::Length(8),
+                Constraint::Length(9),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(Row::new(["Score", "Label", "Decision", "Path"]).bold())
+        .block(
+            Block::bordered().title(format!("{} files, least certain first", self.records.len())),
+        )
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, files, &mut self.file);
+
+        let windows = self
+            .selected()
+            .map(|record| record.windows.clone())
+            .unwrap_or_default();
+        let rows = windows.iter().map(|window| {
+            Row::new([
+                format!("{:.1} - {:.1} s", window.start, window.end),
+                format!("{:?}", window.prediction.label),
+                format!("{:.2}", window.prediction.score()),
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(18),
+                Constraint::Length(8),
+                Constraint::Length(6),
+            ],
+        )
+        .block(Block::bordered().title("Segments"))
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, segments, &mut self.segment);
+
+        let text = vec![Line::from(self.status.as_str()), Line::from(HELP).dim()];
+        frame.render_widget(Paragraph::new(text), status);
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !self.key(key.code) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Lets a reviewer go through `records`, least certain first, recording
+/// their decisions in `overrides`.
+pub fn review(mut records: Vec<Record>, overrides: &mut Overrides) -> Result<()> {
+    records.sort_by(|a, b| uncertainty(b).total_cmp(&uncertainty(a)));
+    let mut review = Review {
+        records,
+        overrides,
+        decisions: HashMap::new(),
+        file: TableState::default(),
+        segment: TableState::default(),
+        player: Player::default(),
+        status: String::new(),
+    };
+    review.select_file(0);
+    let mut terminal = ratatui::try_init().with_context(|| "Failed to set up the terminal")?;
+    let result = review.run(&mut terminal);
+    ratatui::restore();
+    result
+}
@@ -0,0 +1,211 @@
+use crate::{
+    archive::contents,
+    decoder::decode,
+    flac,
+    processing::{file_name, Label, Record},
+};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use hound::{SampleFormat, WavSpec, WavWriter};
+use serde::Serialize;
+use std::{
+    fs::{create_dir_all, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SplitFormat {
+    /// 16-bit PCM WAV
+    #[default]
+    Wav,
+    /// 16-bit FLAC
+    Flac,
+}
+
+impl SplitFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            SplitFormat::Wav => "wav",
+            SplitFormat::Flac => "flac",
+        }
+    }
+}
+
+/// Name of the manifest written next to the segments.
+pub const MANIFEST: &str = "manifest.csv";
+
+#[derive(Debug, Clone)]
+pub struct SplitOptions {
+    /// The label of the windows to keep.
+    pub label: Label,
+    pub dir: PathBuf,
+    /// Where the sources were found, whose layout is mirrored in `dir`.
+    pub root: PathBuf,
+    pub format: SplitFormat,
+    /// Seconds added before and after every segment.
+    pub padding: f64,
+    /// Segments shorter than this many seconds, before padding, are dropped.
+    pub min_length: f64,
+    /// Segments this many seconds apart or closer are joined.
+    pub merge_gap: f64,
+}
+
+/// A stretch of a file to cut out, in seconds from its start, with the mean
+/// score of its windows.
+#[derive(Debug, Clone, Copy)]
+pub struct Span {
+    pub start: f64,
+    pub end: f64,
+    pub score: f32,
+    windows: usize,
+}
+
+/// Joins spans in order of their start whenever the next one starts at most
+/// `gap` seconds after the end of the last.
+fn merge(spans: Vec<Span>, gap: f64) -> Vec<Span> {
+    let mut merged: Vec<Span> = Vec::new();
+    for span in spans {
+        match merged.last_mut() {
+            Some(last) if span.start - last.end <= gap => {
+                let windows = last.windows + span.windows;
+                last.score = (last.score * last.windows as f32 + span.score * span.windows as f32)
+                    / windows as f32;
+                last.windows = windows;
+                last.end = last.end.max(span.end);
+            }
+            _ => merged.push(span),
+        }
+    }
+    merged
+}
+
+/// The stretches of a windowed record labelled `options.label`, merged,
+/// filtered and padded as configured.
+pub fn spans(record: &Record, options: &SplitOptions) -> Vec<Span> {
+    let windows = record
+        .windows
+        .iter()
+        .filter(|window| window.prediction.label == options.label)
+        .map(|window| Span {
+            start: window.start,
+            end: window.end,
+            score: window.prediction.scores.of(options.label),
+            windows: 1,
+        })
+        .collect();
+    let duration = record.metrics.duration;
+    let padded = merge(windows, options.merge_gap)
+        .into_iter()
+        .filter(|span| span.end - span.start >= options.min_length)
+        .map(|span| Span {
+            start: (span.start - options.padding).max(0.0),
+            end: (span.end + options.padding).min(duration),
+            ..span
+        })
+        .collect();
+    // Padding may make neighbours overlap.
+    merge(padded, 0.0)
+}
+
+/// A segment written by `split`, with where it came from.
+#[derive(Debug, Clone, Serialize)]
+pub struct Piece {
+    pub path: PathBuf,
+    pub source: PathBuf,
+    pub label: Label,
+    pub start: f64,
+    pub end: f64,
+    pub score: f32,
+}
+
+fn write_wav(path: &Path, samples: &[f32], channels: usize, sample_rate: u32) -> Result<()> {
+    let spec = WavSpec {
+        channels: channels as u16,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut writer = WavWriter::new(BufWriter::new(create_new(path)?), spec)?;
+    for &sample in samples {
+        writer.write_sample((sample * 32768.0).round().clamp(-32768.0, 32767.0) as i16)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+/// Creates `path`, failing if something is there already.
+fn create_new(path: &Path) -> Result<File> {
+    File::create_new(path).with_context(|| format!("Not replacing {:?}", path))
+}
+
+/// Decodes the file of `record` again and writes every span of it to
+/// `options.dir` as `<name>.<n>.<format>`, at the source's sample rate and
+/// channels. The segments go in the same directory relative to `dir` as the
+/// source is relative to `options.root`, and keep the source's full name, so
+/// no two sources share a segment.
+pub fn split(record: &Record, spans: &[Span], options: &SplitOptions) -> Result<Vec<Piece>> {
+    if spans.is_empty() {
+        return Ok(Vec::new());
+    }
+    let path = &record.path;
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    let decoded = decode(contents(path)?, extension.as_deref())
+        .with_context(|| format!("Failed to decode {:?}", path))?;
+    let channels = decoded.channels.max(1);
+    let at = |seconds: f64| {
+        let frame = (seconds * decoded.sample_rate as f64).round() as usize;
+        (frame * channels).min(decoded.samples.len())
+    };
+    let relative = path
+        .strip_prefix(&options.root)
+        .ok()
+        .and_then(Path::parent)
+        .unwrap_or(Path::new(""));
+    let dir = options.dir.join(relative);
+    create_dir_all(&dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    let name = file_name(path);
+
+    let mut pieces = Vec::with_capacity(spans.len());
+    for (index, span) in spans.iter().enumerate() {
+        let samples = &decoded.samples[at(span.start)..at(span.end)];
+        let file = relative.join(format!(
+            "{}.{}.{}",
+            name,
+            index + 1,
+            options.format.extension()
+        ));
+        let output = options.dir.join(&file);
+        match options.format {
+            SplitFormat::Wav => write_wav(&output, samples, channels, decoded.sample_rate),
+            SplitFormat::Flac => flac::encode(samples, channels, decoded.sample_rate)
+                .and_then(|bytes| Ok(create_new(&output)?.write_all(&bytes)?)),
+        }
+        .with_context(|| format!("Failed to write {:?}", output))?;
+        pieces.push(Piece {
+            path: file,
+            source: path.clone(),
+            label: options.label,
+            start: span.start,
+            end: span.end,
+            score: span.score,
+        });
+    }
+    Ok(pieces)
+}
+
+/// Writes the manifest of `pieces` to `dir`, with their paths relative to it.
+pub fn write_manifest(dir: &Path, pieces: &[Piece]) -> Result<()> {
+    create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    let path = dir.join(MANIFEST);
+    let mut writer =
+        csv::Writer::from_path(&path).with_context(|| format!("Failed to create {:?}", path))?;
+    for piece in pieces {
+        writer.serialize(piece)?;
+    }
+    writer
+        .flush()
+        .with_context(|| format!("Failed to write {:?}", path))
+}
@@ -0,0 +1,268 @@
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+const BUFFER_SIZE: usize = 1 << 16;
+
+/// The size and SHA-256 of a file's contents.
+#[derive(Debug, PartialEq, Eq)]
+struct Fingerprint {
+    size: u64,
+    sha256: [u8; 32],
+}
+
+/// Where a file is written before it is renamed to `destination`, so that
+/// an interrupted run never leaves a partial file under the real name.
+fn partial(destination: &Path) -> PathBuf {
+    let name = destination
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    destination.with_file_name(format!(".{}.{}.partial", name, process::id()))
+}
+
+/// Reads everything from `reader`, writing it to `writer` if given.
+fn stream(mut reader: impl Read, mut writer: Option<&mut File>) -> io::Result<Fingerprint> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut size = 0;
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buffer[..read]);
+        if let Some(ref mut writer) = writer {
+            writer.write_all(&buffer[..read])?;
+        }
+        size += read as u64;
+    }
+    Ok(Fingerprint {
+        size,
+        sha256: hasher.finalize().into(),
+    })
+}
+
+/// Syncs the directory holding `path`, so that a rename into it survives a
+/// crash. Only Unix can open directories for this.
+fn sync_dir(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .with_context(|| format!("Failed to sync {:?}", dir))?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Copies `source` to `temporary` with its permissions and modification
+/// time, synced to disk, and returns the fingerprint of what was read.
+fn copy_to(source: &Path, temporary: &Path) -> Result<Fingerprint> {
+    let mut reader = File::open(source).with_context(|| format!("Failed to open {:?}", source))?;
+    let metadata = reader
+        .metadata()
+        .with_context(|| format!("Failed to read metadata of {:?}", source))?;
+    let mut writer =
+        File::create(temporary).with_context(|| format!("Failed to create {:?}", temporary))?;
+    let fingerprint = stream(&mut reader, Some(&mut writer))
+        .with_context(|| format!("Failed to copy {:?} to {:?}", source, temporary))?;
+    writer
+        .set_permissions(metadata.permissions())
+        .with_context(|| format!("Failed to set permissions of {:?}", temporary))?;
+    if let Ok(modified) = metadata.modified() {
+        writer
+            .set_modified(modified)
+            .with_context(|| format!("Failed to set modification time of {:?}", temporary))?;
+    }
+    writer
+        .sync_all()
+        .with_context(|| format!("Failed to sync {:?}", temporary))?;
+    Ok(fingerprint)
+}
+
+/// Runs `write` on the partial file of `destination` and renames it into
+/// place, or removes it if anything fails.
+fn through_partial(destination: &Path, write: impl FnOnce(&Path) -> Result<()>) -> Result<()> {
+    let temporary = partial(destination);
+    let result = write(&temporary).and_then(|()| {
+        fs::rename(&temporary, destination)
+            .with_context(|| format!("Failed to rename {:?} to {:?}", temporary, destination))
+    });
+    if result.is_err() {
+        _ = fs::remove_file(&temporary);
+    }
+    result?;
+    sync_dir(destination)
+}
+
+/// Copies a file with its permissions and modification time. The copy only
+/// appears under its name once complete.
+pub fn copy(source: &Path, destination: &Path) -> Result<()> {
+    through_partial(destination, |temporary| {
+        copy_to(source, temporary).map(drop)
+    })
+}
+
+/// Writes `bytes` to `destination` through a partial file, like `copy`.
+pub fn write(destination: &Path, bytes: &[u8]) -> Result<()> {
+    through_partial(destination, |temporary| {
+        let mut file =
+            File::create(temporary).with_context(|| format!("Failed to create {:?}", temporary))?;
+        file.write_all(bytes)
+            .and_then(|()| file.sync_all())
+            .with_context(|| format!("Failed to write {:?}", temporary))
+    })
+}
+
+/// Renames a file, or when `destination` is on another filesystem, copies
+/// it there, checks the copy's size and SHA-256 against the source and only
+/// then deletes the source.
+pub fn move_file(source: &Path, destination: &Path) -> Result<()> {
+    finish_move(source, destination, fs::rename(source, destination))
+}
+
+/// Finishes a move after trying to rename `source` to `destination`,
+/// falling back to a verified copy if they are on different filesystems.
+fn finish_move(source: &Path, destination: &Path, renamed: io::Result<()>) -> Result<()> {
+    match renamed {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {}
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to move {:?} to {:?}", source, destination))
+        }
+    }
+    through_partial(destination, |temporary| {
+        let expected = copy_to(source, temporary)?;
+        let file =
+            File::open(temporary).with_context(|| format!("Failed to open {:?}", temporary))?;
+        let actual =
+            stream(file, None).with_context(|| format!("Failed to read back {:?}", temporary))?;
+        if actual != expected {
+            bail!(
+                "The copy of {:?} at {:?} differs from it",
+                source,
+                temporary
+            );
+        }
+        let size = fs::metadata(source)
+            .with_context(|| format!("Failed to read metadata of {:?}", source))?
+            .len();
+        if size != expected.size {
+            bail!("{:?} changed while it was copied", source);
+        }
+        Ok(())
+    })?;
+    fs::remove_file(source).with_context(|| format!("Failed to delete {:?}", source))
+}
+
+/// Moves a file like `move_file`, but fails instead of replacing a file
+/// already at `destination`.
+pub fn move_new(source: &Path, destination: &Path) -> Result<()> {
+    if fs::symlink_metadata(destination).is_ok() {
+        bail!("Not moving {:?} over {:?}", source, destination);
+    }
+    move_file(source, destination)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{copy, finish_move, move_new, through_partial};
+    use anyhow::bail;
+    use std::{
+        fs,
+        io::{Error, ErrorKind},
+        path::{Path, PathBuf},
+        time::{Duration, SystemTime},
+    };
+    use tempfile::tempdir;
+
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    fn modified(path: &Path) -> SystemTime {
+        fs::metadata(path).unwrap().modified().unwrap()
+    }
+
+    /// A source file modified well before the test ran, so that a copy
+    /// stamped with the current time is told apart.
+    fn source(dir: &Path) -> PathBuf {
+        let path = dir.join("a.wav");
+        fs::write(&path, b"RIFF and then some").unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000))
+            .unwrap();
+        path
+    }
+
+    #[test]
+    fn move_new_never_replaces_a_file() {
+        let dir = tempdir().unwrap();
+        let source = source(dir.path());
+        let destination = dir.path().join("b.wav");
+        fs::write(&destination, b"already here").unwrap();
+
+        assert!(move_new(&source, &destination).is_err());
+        assert_eq!(fs::read(&source).unwrap(), b"RIFF and then some");
+        assert_eq!(fs::read(&destination).unwrap(), b"already here");
+    }
+
+    #[test]
+    fn failed_writes_leave_no_partial_file() {
+        let dir = tempdir().unwrap();
+        let destination = dir.path().join("b.wav");
+        let result = through_partial(&destination, |temporary| {
+            fs::write(temporary, b"RIFF and th").unwrap();
+            bail!("Interrupted");
+        });
+
+        assert!(result.is_err());
+        assert!(names(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn copies_keep_contents_and_modification_time() {
+        let dir = tempdir().unwrap();
+        let source = source(dir.path());
+        let destination = dir.path().join("b.wav");
+        copy(&source, &destination).unwrap();
+
+        assert_eq!(fs::read(&destination).unwrap(), b"RIFF and then some");
+        assert_eq!(modified(&destination), modified(&source));
+        assert_eq!(names(dir.path()), ["a.wav", "b.wav"]);
+    }
+
+    #[test]
+    fn moves_across_filesystems_by_verified_copy() {
+        let dir = tempdir().unwrap();
+        let source = source(dir.path());
+        let mtime = modified(&source);
+        let destination = dir.path().join("b.wav");
+        let crossed = Err(Error::from(ErrorKind::CrossesDevices));
+        finish_move(&source, &destination, crossed).unwrap();
+
+        assert_eq!(fs::read(&destination).unwrap(), b"RIFF and then some");
+        assert_eq!(modified(&destination), mtime);
+        assert_eq!(names(dir.path()), ["b.wav"]);
+
+        // Any other failure to rename is reported, and nothing is copied.
+        let denied = Err(Error::from(ErrorKind::PermissionDenied));
+        assert!(finish_move(&destination, &source, denied).is_err());
+        assert_eq!(names(dir.path()), ["b.wav"]);
+    }
+}
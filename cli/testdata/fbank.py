@@ -0,0 +1,48 @@
+"""Writes fbank-<rate>.f32 next to this script: the features torchaudio
+computes for the tones of the resampling tests in src/audio.rs, resampled
+from every input rate to 16 kHz with the settings of `--resampler
+kaiser-best`. Run it with torchaudio installed; `cargo test` compares
+janitor's features with them."""
+
+from math import pi
+from os import path
+
+import torch
+from torchaudio.compliance import kaldi
+from torchaudio.functional import resample
+
+SAMPLE_RATE = 16000
+RATES = [8000, 22050, 44100, 48000, 96000]
+BAND = [i * 120.0 for i in range(1, 30)]
+ABOVE = [9000.0, 10500.0, 12000.0, 13500.0, 15000.0, 18000.0, 21000.0]
+
+
+def generate_tones(sample_rate, duration, frequencies):
+    frequencies = [f for f in frequencies if f < sample_rate * 0.45]
+    t = torch.arange(int(sample_rate * duration), dtype=torch.float64) / sample_rate
+    tones = sum(torch.sin(2 * pi * f * t) for f in frequencies) / 40.0
+    return tones.to(torch.float32)
+
+
+for sample_rate in RATES:
+    audio = resample(
+        generate_tones(sample_rate, 0.5, BAND + ABOVE),
+        sample_rate,
+        SAMPLE_RATE,
+        lowpass_filter_width=64,
+        rolloff=0.9475937167399596,
+        resampling_method="sinc_interp_kaiser",
+        beta=14.769656459379492,
+    )
+    fbank = kaldi.fbank(
+        audio.unsqueeze(0),
+        htk_compat=True,
+        sample_frequency=SAMPLE_RATE,
+        use_energy=False,
+        window_type="hanning",
+        num_mel_bins=128,
+        dither=0.0,
+        frame_shift=10,
+    )
+    out = path.join(path.dirname(__file__), f"fbank-{sample_rate}.f32")
+    fbank.numpy().astype("<f4").tofile(out)
@@ -0,0 +1,140 @@
+"""Writes the same fbank-<rate>.f32 files as fbank.py without torch: a plain
+Python transcription of torchaudio's `functional.resample` with
+`sinc_interp_kaiser` and of `compliance.kaldi.fbank`, computed in double
+precision. It is slower but needs nothing installed."""
+
+from cmath import exp as cexp
+from math import ceil, cos, gcd, log, pi, sin, sqrt
+from operator import mul
+from os import path
+from struct import pack, unpack
+
+SAMPLE_RATE = 16000
+RATES = [8000, 22050, 44100, 48000, 96000]
+BAND = [i * 120.0 for i in range(1, 30)]
+ABOVE = [9000.0, 10500.0, 12000.0, 13500.0, 15000.0, 18000.0, 21000.0]
+
+LOWPASS_FILTER_WIDTH = 64
+ROLLOFF = 0.9475937167399596
+BETA = 14.769656459379492
+
+NUM_MEL_BINS = 128
+WINDOW_SIZE = 400
+WINDOW_SHIFT = 160
+PADDED_WINDOW_SIZE = 512
+PREEMPHASIS = 0.97
+LOW_FREQ = 20.0
+EPSILON = 1.1920928955078125e-07
+
+
+def to_f32(values):
+    return list(unpack(f"<{len(values)}f", pack(f"<{len(values)}f", *values)))
+
+
+def generate_tones(sample_rate, duration, frequencies):
+    frequencies = [f for f in frequencies if f < sample_rate * 0.45]
+    samples = []
+    for i in range(int(sample_rate * duration)):
+        t = i / sample_rate
+        samples.append(sum(sin(2 * pi * f * t) for f in frequencies) / 40.0)
+    return to_f32(samples)
+
+
+def i0(x):
+    total, term, k = 1.0, 1.0, 1
+    while True:
+        term *= (x / (2 * k)) ** 2
+        total += term
+        if term < total * 1e-17:
+            return total
+        k += 1
+
+
+def resample(samples, orig_freq, new_freq):
+    """torchaudio.functional._get_sinc_resample_kernel and
+    _apply_sinc_resample_kernel."""
+    divisor = gcd(orig_freq, new_freq)
+    orig, new = orig_freq // divisor, new_freq // divisor
+    base = min(orig, new) * ROLLOFF
+    width = ceil(LOWPASS_FILTER_WIDTH * orig / base)
+    scale = base / orig
+    kernels = []
+    for phase in range(new):
+        kernel = []
+        for k in range(-width, width + orig):
+            t = (-phase / new + k / orig) * base
+            t = max(-LOWPASS_FILTER_WIDTH, min(LOWPASS_FILTER_WIDTH, t))
+            window = i0(BETA * sqrt(1 - (t / LOWPASS_FILTER_WIDTH) ** 2)) / i0(BETA)
+            t *= pi
+            kernel.append((1.0 if t == 0 else sin(t) / t) * window * scale)
+        kernels.append(kernel)
+    padded = [0.0] * width + samples + [0.0] * (width + orig)
+    taps = 2 * width + orig
+    output = []
+    for start in range(0, len(padded) - taps + 1, orig):
+        frame = padded[start : start + taps]
+        for kernel in kernels:
+            output.append(sum(map(mul, frame, kernel)))
+    return to_f32(output[: ceil(new * len(samples) / orig)])
+
+
+def fft(values):
+    n = len(values)
+    if n == 1:
+        return values
+    even, odd = fft(values[0::2]), fft(values[1::2])
+    twiddled = [cexp(-2j * pi * k / n) * odd[k] for k in range(n // 2)]
+    return [even[k] + twiddled[k] for k in range(n // 2)] + [
+        even[k] - twiddled[k] for k in range(n // 2)
+    ]
+
+
+def mel(frequency):
+    return 1127.0 * log(1.0 + frequency / 700.0)
+
+
+def mel_banks():
+    """torchaudio.compliance.kaldi.get_mel_banks without VTLN, padded with
+    a zero for the Nyquist bin."""
+    low, high = mel(LOW_FREQ), mel(SAMPLE_RATE / 2)
+    delta = (high - low) / (NUM_MEL_BINS + 1)
+    bin_width = SAMPLE_RATE / PADDED_WINDOW_SIZE
+    banks = []
+    for b in range(NUM_MEL_BINS):
+        left, center, right = low + b * delta, low + (b + 1) * delta, low + (b + 2) * delta
+        bank = []
+        for i in range(PADDED_WINDOW_SIZE // 2):
+            m = mel(bin_width * i)
+            up = (m - left) / (center - left)
+            down = (right - m) / (right - center)
+            bank.append(max(0.0, min(up, down)))
+        banks.append(bank + [0.0])
+    return banks
+
+
+def fbank(samples):
+    """torchaudio.compliance.kaldi.fbank with the options of knfc.cc:
+    htk_compat, no energy, Hanning window and no dither."""
+    window = [
+        0.5 - 0.5 * cos(2 * pi * n / (WINDOW_SIZE - 1)) for n in range(WINDOW_SIZE)
+    ]
+    banks = mel_banks()
+    frames = []
+    for f in range(1 + (len(samples) - WINDOW_SIZE) // WINDOW_SHIFT):
+        frame = samples[f * WINDOW_SHIFT : f * WINDOW_SHIFT + WINDOW_SIZE]
+        mean = sum(frame) / WINDOW_SIZE
+        frame = [x - mean for x in frame]
+        frame = [x - PREEMPHASIS * y for x, y in zip(frame, [frame[0]] + frame[:-1])]
+        frame = [x * w for x, w in zip(frame, window)]
+        spectrum = fft(frame + [0.0] * (PADDED_WINDOW_SIZE - WINDOW_SIZE))
+        power = [abs(c) ** 2 for c in spectrum[: PADDED_WINDOW_SIZE // 2 + 1]]
+        frames.append([log(max(sum(map(mul, power, bank)), EPSILON)) for bank in banks])
+    return frames
+
+
+for sample_rate in RATES:
+    audio = resample(generate_tones(sample_rate, 0.5, BAND + ABOVE), sample_rate, SAMPLE_RATE)
+    values = [value for frame in fbank(audio) for value in frame]
+    out = path.join(path.dirname(__file__), f"fbank-{sample_rate}.f32")
+    with open(out, "wb") as file:
+        file.write(pack(f"<{len(values)}f", *values))
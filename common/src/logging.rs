@@ -0,0 +1,93 @@
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::{
+    fs::OpenOptions,
+    io::{stderr, IsTerminal},
+    path::Path,
+    sync::Mutex,
+};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{fmt, EnvFilter};
+
+/// Environment variable holding a filter such as `janitor=debug,reqwest=info`,
+/// which wins over the level given by `-v` and `-q`.
+pub const ENV_FILTER: &str = "JANITOR_LOG";
+
+/// The crates whose events the level applies to. Other crates only log
+/// warnings and errors unless `JANITOR_LOG` says otherwise.
+const CRATES: [&str; 5] = [
+    "janitor",
+    "janitor_cli",
+    "janitor_common",
+    "janitor_model",
+    "janitor_service",
+];
+
+/// From quietest to most verbose.
+const LEVELS: [LevelFilter; 6] = [
+    LevelFilter::OFF,
+    LevelFilter::ERROR,
+    LevelFilter::WARN,
+    LevelFilter::INFO,
+    LevelFilter::DEBUG,
+    LevelFilter::TRACE,
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    /// One human readable line per event
+    #[default]
+    Human,
+    /// One JSON object per event
+    Json,
+}
+
+/// `default`, with every `verbose` making it one step more verbose and
+/// every `quiet` one step quieter.
+pub fn level(default: LevelFilter, verbose: u8, quiet: u8) -> LevelFilter {
+    let default = LEVELS
+        .iter()
+        .position(|&level| level == default)
+        .unwrap_or(0);
+    let index = default as i16 + verbose as i16 - quiet as i16;
+    LEVELS[index.clamp(0, LEVELS.len() as i16 - 1) as usize]
+}
+
+/// Logs events of janitor's crates at `level`, or as `JANITOR_LOG` says, to
+/// standard error or appended to `file`.
+pub fn init(level: LevelFilter, format: LogFormat, file: Option<&Path>) -> Result<()> {
+    let filter = match std::env::var(ENV_FILTER) {
+        Ok(directives) => EnvFilter::try_new(&directives)
+            .with_context(|| format!("Invalid {} {:?}", ENV_FILTER, directives))?,
+        Err(_) => {
+            let mut directives = LevelFilter::WARN.min(level).to_string();
+            for name in CRATES {
+                directives += &format!(",{}={}", name, level);
+            }
+            EnvFilter::new(directives)
+        }
+    };
+    let file = file
+        .map(|path| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open log file {:?}", path))
+        })
+        .transpose()?;
+    // Colours only when a person is likely to read the events.
+    let builder = fmt()
+        .with_env_filter(filter)
+        .with_ansi(file.is_none() && stderr().is_terminal());
+    match (format, file) {
+        (LogFormat::Human, None) => builder.with_writer(stderr).try_init(),
+        (LogFormat::Human, Some(file)) => builder.with_writer(Mutex::new(file)).try_init(),
+        (LogFormat::Json, None) => builder.json().with_writer(stderr).try_init(),
+        (LogFormat::Json, Some(file)) => builder.json().with_writer(Mutex::new(file)).try_init(),
+    }
+    .map_err(|e| anyhow!(e))
+    .with_context(|| "Failed to set up logging")
+}
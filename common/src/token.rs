@@ -0,0 +1,15 @@
+use anyhow::{Context, Result};
+use std::{fs::read_to_string, path::Path};
+
+/// The token given directly, or else read from `file`.
+pub fn load(token: Option<String>, file: Option<&Path>) -> Result<Option<String>> {
+    if token.is_some() {
+        return Ok(token);
+    }
+    file.map(|file| {
+        read_to_string(file)
+            .map(|token| token.trim().to_string())
+            .with_context(|| format!("Failed to read token from {:?}", file))
+    })
+    .transpose()
+}
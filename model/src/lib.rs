@@ -0,0 +1,5 @@
+mod model;
+pub use model::{Embedding, Label, Model, Prediction, Scores};
+
+mod tensor;
+pub use tensor::{fit, from_fbank, normalize, NUM_FRAMES, NUM_MEL_BINS};
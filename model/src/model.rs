@@ -0,0 +1,138 @@
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use serde::Serialize;
+use std::{path::Path, time::Instant};
+use tch::{autocast, no_grad, CModule, Device, Kind, Tensor};
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub enum Label {
+    Speech,
+    Music,
+    Noise,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Scores {
+    pub speech: f32,
+    pub music: f32,
+    pub noise: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Prediction {
+    /// The label scored highest of `labels`.
+    pub label: Label,
+    /// Every label scored at least its threshold, highest first, or noise
+    /// when none is.
+    pub labels: Vec<Label>,
+    pub scores: Scores,
+}
+
+/// The model's pooled representation of an input, for comparing inputs with
+/// each other rather than labelling them.
+#[derive(Debug, Clone, Serialize)]
+pub struct Embedding {
+    pub vector: Vec<f32>,
+}
+
+pub struct Model {
+    model: CModule,
+    thresholds: Scores,
+}
+
+impl Model {
+    pub fn new<T>(model_path: T) -> Result<Model>
+    where
+        T: AsRef<Path>,
+    {
+        let device = Device::cuda_if_available();
+        let started = Instant::now();
+        let model = CModule::load_on_device(model_path, device)?;
+        info!(?device, elapsed = ?started.elapsed(), "Loaded model");
+        Ok(Self {
+            model,
+            thresholds: Scores {
+                speech: 0.5,
+                music: 0.5,
+                noise: 0.5,
+            },
+        })
+    }
+
+    /// Sets the score from which every label is reported, 0.5 by default.
+    pub fn with_thresholds(mut self, thresholds: Scores) -> Model {
+        self.thresholds = thresholds;
+        self
+    }
+
+    fn labels(&self, scores: &Scores) -> Vec<Label> {
+        let labels = [
+            (Label::Speech, scores.speech, self.thresholds.speech),
+            (Label::Music, scores.music, self.thresholds.music),
+            (Label::Noise, scores.noise, self.thresholds.noise),
+        ]
+        .into_iter()
+        .filter(|(_, score, threshold)| score >= threshold)
+        .sorted_by(|a, b| b.1.total_cmp(&a.1))
+        .map(|(label, _, _)| label)
+        .collect_vec();
+        if labels.is_empty() {
+            vec![Label::Noise]
+        } else {
+            labels
+        }
+    }
+
+    /// Labels a single `(frames, NUM_MEL_BINS)` tensor.
+    pub fn label_one(&self, tensor: &Tensor) -> Result<Prediction> {
+        let batch = tensor.f_unsqueeze(0)?;
+        self.label(&batch)?
+            .into_vec()
+            .pop()
+            .with_context(|| "Model returned no prediction")
+    }
+
+    pub fn label(&self, tensor: &Tensor) -> Result<Box<[Prediction]>> {
+        let output = no_grad(|| autocast(true, || tensor.apply(&self.model))).f_sigmoid()?;
+        let labels = Vec::try_from(output.flatten(0, -1))?
+            .chunks(527)
+            .map(|chunk| {
+                let output: [f32; 527] = chunk.try_into().unwrap();
+                let scores = Scores {
+                    speech: output[0],
+                    music: output[137],
+                    noise: output[513],
+                };
+                let labels = self.labels(&scores);
+                Prediction {
+                    label: labels[0],
+                    labels,
+                    scores,
+                }
+            })
+            .collect_vec()
+            .into_boxed_slice();
+        Ok(labels)
+    }
+
+    /// Embeds a single `(frames, NUM_MEL_BINS)` tensor.
+    pub fn embed_one(&self, tensor: &Tensor) -> Result<Embedding> {
+        let batch = tensor.f_unsqueeze(0)?;
+        self.embed(&batch)?
+            .into_vec()
+            .pop()
+            .with_context(|| "Model returned no embedding")
+    }
+
+    /// Runs the `embed` method exported with the model, which returns the
+    /// pooled representation its classification head is applied to.
+    pub fn embed(&self, tensor: &Tensor) -> Result<Box<[Embedding]>> {
+        let output = no_grad(|| autocast(true, || self.model.method_ts("embed", &[tensor])))?;
+        let vectors: Vec<Vec<f32>> = Vec::try_from(output.f_to_kind(Kind::Float)?)?;
+        Ok(vectors
+            .into_iter()
+            .map(|vector| Embedding { vector })
+            .collect())
+    }
+}
@@ -0,0 +1,37 @@
+use anyhow::{bail, Result};
+use std::cmp::Ordering;
+use tch::{Device, Tensor};
+
+pub const NUM_FRAMES: i64 = 1024;
+pub const NUM_MEL_BINS: i64 = 128;
+
+/// Builds a `(frames, NUM_MEL_BINS)` tensor from row-major filter bank values.
+pub fn from_fbank(fbank: &[f32]) -> Result<Tensor> {
+    if !fbank.len().is_multiple_of(NUM_MEL_BINS as usize) {
+        bail!("Expected a multiple of {} values", NUM_MEL_BINS);
+    }
+    let num_frames = (fbank.len() / NUM_MEL_BINS as usize) as i64;
+    let tensor = Tensor::f_from_slice(fbank)?
+        .f_reshape([num_frames, NUM_MEL_BINS])?
+        .f_to(Device::cuda_if_available())?;
+    Ok(tensor)
+}
+
+pub fn fit(tensor: &mut Tensor) -> Result<()> {
+    let (num_frames, _) = tensor.size2()?;
+    match NUM_FRAMES.cmp(&num_frames) {
+        Ordering::Less => *tensor = tensor.f_narrow(0, 0, NUM_FRAMES)?,
+        Ordering::Greater => {
+            *tensor = tensor.f_pad([0, 0, 0, NUM_FRAMES - num_frames], "constant", Some(0.0))?
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+pub fn normalize(tensor: &mut Tensor) -> Result<()> {
+    *tensor = tensor
+        .f_subtract_scalar(-4.2677393)?
+        .f_divide_scalar(4.5689974 * 2.0)?;
+    Ok(())
+}
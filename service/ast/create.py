@@ -22,4 +22,6 @@ state_dict = {
 model.load_state_dict(state_dict)
 
 torch_script_module = jit.script(model)
+# janitor-service serves `embed` on /embed next to `forward`.
+assert hasattr(torch_script_module, "embed"), "embed was not exported"
 torch_script_module.save("./model.pt")
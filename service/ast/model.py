@@ -333,13 +333,11 @@ class ASTModel(nn.Module):
         t_dim = test_out.shape[3]
         return f_dim, t_dim
 
-    @autocast("cuda")
-    def forward(self, x):
+    def pool(self, x):
         """
         :param x: the input spectrogram, expected shape: (batch_size, time_frame_num, frequency_bins), e.g., (12, 1024, 128)
-        :return: prediction
+        :return: the mean of the cls and distillation tokens, which the mlp head is applied to
         """
-        # expect input x = (batch_size, time_frame_num, frequency_bins), e.g., (12, 1024, 128)
         x = x.unsqueeze(1)
         x = x.transpose(2, 3)
 
@@ -354,7 +352,21 @@ class ASTModel(nn.Module):
             x = blk(x)
         x = self.v.norm(x)
         x = (x[:, 0] + x[:, 1]) / 2
+        return x
 
-        x = self.mlp_head(x)
+    @autocast("cuda")
+    def forward(self, x):
+        """
+        :param x: the input spectrogram, expected shape: (batch_size, time_frame_num, frequency_bins), e.g., (12, 1024, 128)
+        :return: prediction
+        """
+        return self.mlp_head(self.pool(x))
 
-        return x
+    @torch.jit.export
+    @autocast("cuda")
+    def embed(self, x):
+        """
+        :param x: the input spectrogram, expected shape: (batch_size, time_frame_num, frequency_bins), e.g., (12, 1024, 128)
+        :return: embedding, expected shape: (batch_size, embedding_dim), e.g., (12, 768)
+        """
+        return self.pool(x)
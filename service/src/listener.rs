@@ -0,0 +1,123 @@
+use anyhow::{bail, Context, Result};
+use axum::{serve, Router};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto::Builder,
+    service::TowerToHyperService,
+};
+use std::{
+    fs::{remove_file, symlink_metadata, File},
+    io::BufReader,
+    os::unix::fs::FileTypeExt,
+    path::Path,
+    sync::Arc,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, UnixListener},
+    spawn,
+    time::{sleep, Duration},
+};
+use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+use tracing::warn;
+
+/// Where the service accepts connections.
+pub enum Listener {
+    Tcp(TcpListener),
+    Tls(TcpListener, TlsAcceptor),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Binds `address`, which is either `host:port` or `unix:///path/to/socket`.
+    pub async fn bind(address: &str, tls: Option<TlsAcceptor>) -> Result<Listener> {
+        let Some(path) = address.strip_prefix("unix://") else {
+            let listener = TcpListener::bind(address)
+                .await
+                .with_context(|| format!("Failed to bind {}", address))?;
+            return Ok(match tls {
+                Some(acceptor) => Listener::Tls(listener, acceptor),
+                None => Listener::Tcp(listener),
+            });
+        };
+        if tls.is_some() {
+            bail!("TLS is not supported on Unix sockets");
+        }
+        // A socket left behind by an earlier run would make binding fail.
+        if symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            remove_file(path).with_context(|| format!("Failed to remove stale socket {}", path))?;
+        }
+        let listener =
+            UnixListener::bind(path).with_context(|| format!("Failed to bind {}", path))?;
+        Ok(Listener::Unix(listener))
+    }
+
+    pub async fn serve(self, router: Router) -> Result<()> {
+        match self {
+            Listener::Tcp(listener) => Ok(serve(listener, router).await?),
+            Listener::Tls(listener, acceptor) => loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        backoff(e).await;
+                        continue;
+                    }
+                };
+                let acceptor = acceptor.clone();
+                let router = router.clone();
+                spawn(async move {
+                    match acceptor.accept(stream).await {
+                        Ok(stream) => connection(stream, router).await,
+                        Err(e) => warn!("TLS handshake failed: {}", e),
+                    }
+                });
+            },
+            Listener::Unix(listener) => loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        spawn(connection(stream, router.clone()));
+                    }
+                    Err(e) => backoff(e).await,
+                }
+            },
+        }
+    }
+}
+
+/// Accept errors are mostly running out of file descriptors, which resolves
+/// itself once other connections close.
+async fn backoff(error: std::io::Error) {
+    warn!("Failed to accept connection: {}", error);
+    sleep(Duration::from_secs(1)).await;
+}
+
+async fn connection<S>(stream: S, router: Router)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = TowerToHyperService::new(router);
+    if let Err(e) = Builder::new(TokioExecutor::new())
+        .serve_connection(TokioIo::new(stream), service)
+        .await
+    {
+        warn!("Connection failed: {}", e);
+    }
+}
+
+/// Loads a PEM certificate chain and private key.
+pub fn tls_acceptor(cert: &Path, key: &Path) -> Result<TlsAcceptor> {
+    let file = File::open(cert).with_context(|| format!("Failed to open {:?}", cert))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to read certificates from {:?}", cert))?;
+    let file = File::open(key).with_context(|| format!("Failed to open {:?}", key))?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to read private key from {:?}", key))?
+        .with_context(|| format!("No private key found in {:?}", key))?;
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .with_context(|| "Invalid certificate or private key")?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
@@ -0,0 +1,21 @@
+use axum::{extract::Request, middleware::Next, response::Response};
+pub use janitor_common::logging::{init, level, LogFormat};
+use tokio::time::Instant;
+use tracing::info;
+
+/// Logs every request once it is answered, with its status and how long
+/// answering it took.
+pub async fn requests(request: Request, next: Next) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let started = Instant::now();
+    let response = next.run(request).await;
+    info!(
+        %method,
+        path,
+        status = response.status().as_u16(),
+        elapsed = ?started.elapsed(),
+        "Answered request"
+    );
+    response
+}
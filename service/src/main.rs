@@ -1,25 +1,56 @@
-use anyhow::Result;
-use axum::{body::Bytes, http::StatusCode, routing::post, serve, Json, Router};
-use clap::Parser;
+use anyhow::{bail, Result};
+use axum::{body::Bytes, http::StatusCode, middleware, routing::post, Json, Router};
+use clap::{ArgAction, Parser};
+use janitor_common::token;
+use janitor_model::{fit, normalize, Embedding, Model, Prediction, Scores};
+use listener::{tls_acceptor, Listener};
+use logging::LogFormat;
 use parse_duration::parse;
-use queue::run;
-use safetensors::SafeTensors;
-use tensor::{fit, normalize, to_tensor};
-use tokio::{net::TcpListener, spawn, task::spawn_blocking};
+use queue::{run, Answer};
+use safetensors::{tensor::TensorView, SafeTensors};
+use serde::Serialize;
+use std::{path::PathBuf, sync::Arc};
+use tch::Tensor;
+use tensor::to_tensor;
+use tokio::{spawn, task::spawn_blocking};
+use tracing::{debug, info, level_filters::LevelFilter};
 
-mod tensor;
+mod auth;
+
+mod listener;
 
-mod model;
-use model::{Label, Model};
+mod logging;
+
+mod tensor;
 
 mod queue;
 
+/// Info events and worse unless `-v` or `-q` say otherwise.
+const LOG_LEVEL: LevelFilter = LevelFilter::INFO;
+
 #[derive(Debug, Parser)]
 #[command(about, long_about = None, version)]
 struct Args {
+    /// `host:port` to listen on, or `unix:///path/to/socket`
     #[arg(short, long, default_value = "0.0.0.0:8000")]
     address: String,
 
+    /// PEM certificate chain to serve HTTPS with
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key of the certificate
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Bearer token clients must send
+    #[arg(long, env = "JANITOR_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// File holding the bearer token clients must send
+    #[arg(long)]
+    token_file: Option<PathBuf>,
+
     #[arg(short, long, default_value = "./ast/model.pt")]
     model_path: String,
 
@@ -28,42 +59,161 @@ struct Args {
 
     #[arg(short, long, default_value = "100ms")]
     timeout: String,
+
+    /// Scores from which speech, music and noise are reported, comma separated
+    #[arg(long, value_delimiter = ',', default_value = "0.5,0.5,0.5")]
+    thresholds: Vec<f32>,
+
+    /// Log more: debug and trace events with one and two
+    #[arg(short, long, action = ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Log less: only warnings, errors and nothing with one, two and three
+    #[arg(short, long, action = ArgAction::Count)]
+    quiet: u8,
+
+    /// How log events are written
+    #[arg(long, value_enum, default_value_t)]
+    log_format: LogFormat,
+
+    /// Append log events to this file instead of standard error
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+}
+
+/// The answer for a tensor of a batch request, or why there is none.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Item<T> {
+    Answer(T),
+    Error { error: String },
+}
+
+/// A single `fbank` tensor is answered with a prediction or embedding, a
+/// batch of `fbank.0` to `fbank.N` tensors with an array of items in the
+/// same order.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Output<T> {
+    Single(T),
+    Batch(Vec<Item<T>>),
+}
+
+enum Input {
+    Single(Tensor),
+    Batch(Vec<Result<Tensor, String>>),
 }
 
-async fn handler(body: Bytes) -> Result<Json<Label>, (StatusCode, String)> {
-    let tensor = spawn_blocking(move || {
+fn prepare(view: TensorView) -> Result<Tensor, (StatusCode, String)> {
+    let mut tensor = to_tensor(view)?;
+    fit(&mut tensor).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    normalize(&mut tensor).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(tensor)
+}
+
+/// Labels the posted fbanks on `/` and embeds them on `/embed`.
+async fn handler<T: Answer + Serialize>(
+    body: Bytes,
+) -> Result<Json<Output<T>>, (StatusCode, String)> {
+    let input = spawn_blocking(move || {
         let tensors = SafeTensors::deserialize(&body[..])
             .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
-        let fbank = tensors
-            .tensor("fbank")
-            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
-        let mut tensor = to_tensor(fbank)?;
-        fit(&mut tensor)?;
-        normalize(&mut tensor)?;
-        Ok(tensor)
+        if let Ok(fbank) = tensors.tensor("fbank") {
+            return Ok(Input::Single(prepare(fbank)?));
+        }
+        let items = (0..)
+            .map_while(|index| tensors.tensor(&format!("fbank.{}", index)).ok())
+            .map(|fbank| prepare(fbank).map_err(|(_, e)| e))
+            .collect::<Vec<_>>();
+        if items.is_empty() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Expected an fbank tensor or fbank.0 to fbank.N".to_string(),
+            ));
+        }
+        Ok(Input::Batch(items))
     })
     .await
     .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))??;
 
-    let result_rx = queue::add(tensor).await;
-    let label = result_rx
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    Ok(Json(label))
+    let items = match input {
+        Input::Single(tensor) => {
+            let result_rx = queue::add(tensor).await;
+            let answer = result_rx
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+                // The model rejected the input, which retrying elsewhere won't fix.
+                .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+            return Ok(Json(Output::Single(answer)));
+        }
+        Input::Batch(items) => items,
+    };
+    debug!(fbanks = items.len(), "Received batch request");
+
+    let mut tensors = Vec::with_capacity(items.len());
+    let slots = items
+        .into_iter()
+        .map(|item| item.map(|tensor| tensors.push(tensor)))
+        .collect::<Vec<_>>();
+    let mut receivers = queue::add_group(tensors).await.into_iter();
+    let mut output = Vec::with_capacity(slots.len());
+    for slot in slots {
+        let item = match slot {
+            Err(error) => Item::Error { error },
+            Ok(()) => {
+                let result_rx = receivers.next().expect("one receiver per queued tensor");
+                match result_rx.await {
+                    Ok(Ok(answer)) => Item::Answer(answer),
+                    Ok(Err(error)) => Item::Error { error },
+                    Err(e) => Item::Error {
+                        error: e.to_string(),
+                    },
+                }
+            }
+        };
+        output.push(item);
+    }
+    Ok(Json(Output::Batch(output)))
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
+    logging::init(
+        logging::level(LOG_LEVEL, args.verbose, args.quiet),
+        args.log_format,
+        args.log_file.as_deref(),
+    )?;
 
     let timeout = parse(&args.timeout)?;
-    let model = Model::new(args.model_path)?;
+    let token = token::load(args.token, args.token_file.as_deref())?;
+    let tls = match (args.tls_cert, args.tls_key) {
+        (Some(cert), Some(key)) => Some(tls_acceptor(&cert, &key)?),
+        _ => None,
+    };
+    let listener = Listener::bind(&args.address, tls).await?;
+
+    let [speech, music, noise] = args.thresholds[..] else {
+        bail!("Expected three thresholds, for speech, music and noise");
+    };
+    let model = Model::new(args.model_path)?.with_thresholds(Scores {
+        speech,
+        music,
+        noise,
+    });
     spawn(async move {
         run(model, args.batch_size, timeout).await;
     });
 
-    let router = Router::new().route("/", post(handler));
+    let mut router = Router::new()
+        .route("/", post(handler::<Prediction>))
+        .route("/embed", post(handler::<Embedding>));
+    if let Some(token) = token {
+        let token: Arc<str> = token.into();
+        router = router.route_layer(middleware::from_fn_with_state(token, auth::authorize));
+    }
+    router = router.layer(middleware::from_fn(logging::requests));
 
-    let listener = TcpListener::bind(args.address).await?;
-    Ok(serve(listener, router).await?)
+    info!(address = args.address, "Listening");
+    listener.serve(router).await
 }
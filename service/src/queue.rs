@@ -1,5 +1,4 @@
 use lazy_static::lazy_static;
-use spinoff::{spinners, Spinner};
 use std::{cmp::min, collections::VecDeque};
 use tch::Tensor;
 use tokio::{
@@ -9,28 +8,64 @@ use tokio::{
     },
     time::{sleep, Duration, Instant},
 };
+use tracing::{debug, error};
 
-use crate::model::{Label, Model};
+use janitor_model::{Embedding, Model, Prediction};
 
-type JobQueue = VecDeque<(Tensor, Sender<Label>)>;
+/// An answer of the model, or why the batch holding its tensor failed.
+pub type Reply<T> = Result<T, String>;
+
+/// A tensor waiting to be labelled or embedded, and where its answer goes.
+pub enum Job {
+    Label(Tensor, Sender<Reply<Prediction>>),
+    Embed(Tensor, Sender<Reply<Embedding>>),
+}
+
+/// What the model can be asked for.
+pub trait Answer: Sized {
+    fn job(tensor: Tensor, result_tx: Sender<Reply<Self>>) -> Job;
+}
+
+impl Answer for Prediction {
+    fn job(tensor: Tensor, result_tx: Sender<Reply<Prediction>>) -> Job {
+        Job::Label(tensor, result_tx)
+    }
+}
+
+impl Answer for Embedding {
+    fn job(tensor: Tensor, result_tx: Sender<Reply<Embedding>>) -> Job {
+        Job::Embed(tensor, result_tx)
+    }
+}
+
+type JobQueue = VecDeque<Job>;
 
 lazy_static! {
     static ref QUEUE: Mutex<JobQueue> = Mutex::new(VecDeque::new());
 }
 
-pub async fn add(tensor: Tensor) -> Receiver<Label> {
+pub async fn add<T: Answer>(tensor: Tensor) -> Receiver<Reply<T>> {
     let (result_tx, result_rx) = channel();
-    let job = (tensor, result_tx);
-    QUEUE.lock().await.push_back(job);
+    QUEUE.lock().await.push_back(T::job(tensor, result_tx));
     result_rx
 }
 
-async fn get_jobs(
-    batch_size: usize,
-    timeout: Duration,
-) -> ((Vec<Tensor>, Vec<Sender<Label>>), usize) {
-    let mut tensors = Vec::with_capacity(batch_size);
-    let mut transmitters = Vec::with_capacity(batch_size);
+/// Queues the tensors of a batch request together, so they end up in the
+/// same model batches instead of interleaving with other requests.
+pub async fn add_group<T: Answer>(tensors: Vec<Tensor>) -> Vec<Receiver<Reply<T>>> {
+    let mut queue = QUEUE.lock().await;
+    tensors
+        .into_iter()
+        .map(|tensor| {
+            let (result_tx, result_rx) = channel();
+            queue.push_back(T::job(tensor, result_tx));
+            result_rx
+        })
+        .collect()
+}
+
+async fn get_jobs(batch_size: usize, timeout: Duration) -> (Vec<Job>, usize) {
+    let mut jobs = Vec::with_capacity(batch_size);
     let mut remaining = batch_size;
     let mut rest = 0;
     let start = Instant::now();
@@ -40,8 +75,7 @@ async fn get_jobs(
         if usable > 0 {
             for _ in 0..usable {
                 if let Some(job) = queue.pop_front() {
-                    tensors.push(job.0);
-                    transmitters.push(job.1);
+                    jobs.push(job);
                 }
             }
             remaining -= usable;
@@ -51,39 +85,80 @@ async fn get_jobs(
             sleep(Duration::from_millis(100)).await;
         }
     }
-    ((tensors, transmitters), rest)
+    (jobs, rest)
+}
+
+/// Runs the model on a batch of tensors and sends every answer to its
+/// transmitter. If the batch fails, every transmitter gets the error.
+fn execute<T>(
+    task: &str,
+    tensors: Vec<Tensor>,
+    transmitters: Vec<Sender<Reply<T>>>,
+    model: impl Fn(&Tensor) -> anyhow::Result<Box<[T]>>,
+) {
+    if tensors.is_empty() {
+        return;
+    }
+    let jobs = tensors.len();
+    let started = Instant::now();
+    let answers = Tensor::f_stack(&tensors, 0)
+        .map_err(|e| format!("Failed to stack tensors: {}", e))
+        .and_then(|tensor| model(&tensor).map_err(|e| format!("Model failed: {:#}", e)));
+    match answers {
+        Ok(answers) => {
+            debug!(task, jobs, elapsed = ?started.elapsed(), "Ran model");
+            let mut answers = answers.into_vec().into_iter();
+            for result_tx in transmitters {
+                let answer = answers
+                    .next()
+                    .ok_or_else(|| "Model returned too few answers".to_string());
+                _ = result_tx.send(answer);
+            }
+        }
+        Err(e) => {
+            error!(task, jobs, "{}", e);
+            for result_tx in transmitters {
+                _ = result_tx.send(Err(e.clone()));
+            }
+        }
+    }
 }
 
 pub async fn run(model: Model, batch_size: usize, timeout: Duration) {
-    let mut spinner = Spinner::new(spinners::Line, "Waiting for jobs", None);
     loop {
-        let ((tensors, transmitters), remaining) = get_jobs(batch_size, timeout).await;
-        if tensors.is_empty() {
+        let (jobs, remaining) = get_jobs(batch_size, timeout).await;
+        if jobs.is_empty() {
             continue;
         }
 
-        let tensor = match Tensor::f_stack(&tensors, 0) {
-            Ok(tensor) => tensor,
-            Err(_) => {
-                continue;
-            }
-        };
-
-        spinner.update_text(format!(
-            "Executing {} job(s) with {} still remaining",
-            tensors.len(),
-            remaining
-        ));
-        let labels = match model.label(&tensor) {
-            Ok(tensor) => tensor,
-            Err(_) => {
-                continue;
+        let started = Instant::now();
+        let count = jobs.len();
+        let mut labels = (Vec::new(), Vec::new());
+        let mut embeddings = (Vec::new(), Vec::new());
+        for job in jobs {
+            match job {
+                Job::Label(tensor, result_tx) => {
+                    labels.0.push(tensor);
+                    labels.1.push(result_tx);
+                }
+                Job::Embed(tensor, result_tx) => {
+                    embeddings.0.push(tensor);
+                    embeddings.1.push(result_tx);
+                }
             }
-        };
-
-        for (&label, result_tx) in labels.iter().zip(transmitters.into_iter()) {
-            _ = result_tx.send(label);
         }
-        spinner.update_text("Waiting for jobs");
+        let (labelled, embedded) = (labels.0.len(), embeddings.0.len());
+        execute("label", labels.0, labels.1, |tensor| model.label(tensor));
+        execute("embed", embeddings.0, embeddings.1, |tensor| {
+            model.embed(tensor)
+        });
+        debug!(
+            jobs = count,
+            labelled,
+            embedded,
+            remaining,
+            elapsed = ?started.elapsed(),
+            "Executed batch"
+        );
     }
 }
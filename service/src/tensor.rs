@@ -1,13 +1,9 @@
-use std::cmp::Ordering;
-
 use anyhow::Result;
 use axum::http::StatusCode;
+use janitor_model::NUM_MEL_BINS;
 use safetensors::{tensor::TensorView, Dtype};
 use tch::{Device, Kind, Tensor};
 
-pub const NUM_FRAMES: i64 = 1024;
-pub const NUM_MEL_BINS: i64 = 128;
-
 pub fn to_tensor(view: TensorView) -> Result<Tensor, (StatusCode, String)> {
     let size: Vec<i64> = view.shape().iter().map(|&x| x as i64).collect();
     let kind = match view.dtype() {
@@ -62,32 +58,3 @@ pub fn check(tensor: &Tensor) -> Result<(), (StatusCode, String)> {
     }
     Ok(())
 }
-
-pub fn fit(tensor: &mut Tensor) -> Result<(), (StatusCode, String)> {
-    let (num_frames, _) = tensor
-        .size2()
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    match NUM_FRAMES.cmp(&num_frames) {
-        Ordering::Less => {
-            *tensor = tensor
-                .f_narrow(0, 0, NUM_FRAMES)
-                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-        }
-        Ordering::Greater => {
-            *tensor = tensor
-                .f_pad([0, 0, 0, NUM_FRAMES - num_frames], "constant", Some(0.0))
-                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-        }
-        _ => {}
-    }
-    Ok(())
-}
-
-pub fn normalize(tensor: &mut Tensor) -> Result<(), (StatusCode, String)> {
-    *tensor = tensor
-        .f_subtract_scalar(-4.2677393)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-        .f_divide_scalar(4.5689974 * 2.0)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    Ok(())
-}